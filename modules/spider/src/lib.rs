@@ -1,11 +1,20 @@
 #[macro_use]
 extern crate plugin_base;
 
-use plugin_base::log::{self, LevelFilter};
-use plugin_base::logger::{ExternalLogger, LogCallback};
-use plugin_base::{abi_stable::std_types::RStr, declare_plugin, Plugin, PluginError};
+use plugin_base::abi_stable::std_types::{RStr, RString, RVec};
+use plugin_base::log::LevelFilter;
+use plugin_base::logger::{init_plugin_logger, LogCallback};
+use plugin_base::{config, declare_plugin, Plugin, PluginError};
+use serde::Deserialize;
 
-struct Spider {}
+#[derive(Deserialize)]
+struct Config {
+    start_url: String,
+}
+
+struct Spider {
+    start_url: String,
+}
 
 impl Plugin for Spider {
     fn name(&self) -> RStr<'static> {
@@ -13,19 +22,27 @@ impl Plugin for Spider {
     }
 
     fn api_version_require(&self) -> RStr<'static> {
-        RStr::from_str("^0.1.0")
+        RStr::from_str("^0.2.0")
     }
 
     fn on_plugin_load(&self) {
-        info!("Plugin loaded");
+        info!("Plugin loaded, crawling from {}", self.start_url);
+    }
+
+    fn interfaces(&self) -> RVec<RString> {
+        RVec::from(vec![RString::from("comet.spider.v1")])
     }
 }
 
-fn create_plugin(_: Option<String>, log_callback: LogCallback) -> Result<Spider, PluginError> {
-    let logger = ExternalLogger::new(log_callback);
-    log::set_boxed_logger(Box::new(logger)).map_err(|_| PluginError::SetLogger)?;
-    log::set_max_level(LevelFilter::Trace);
-    Ok(Spider {})
+fn create_plugin(config: Option<String>, log_callback: LogCallback) -> Result<Spider, PluginError> {
+    // emit everything, the host decides per plugin what it keeps,
+    // e.g. `set_plugin_log_level("spider", LevelFilter::Warn)`
+    init_plugin_logger(log_callback, LevelFilter::Trace)?;
+    let config: Config = config::parse_json(config)?;
+    debug!("Spider created");
+    Ok(Spider {
+        start_url: config.start_url,
+    })
 }
 
 declare_plugin!(Spider, create_plugin);
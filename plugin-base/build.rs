@@ -0,0 +1,15 @@
+//! Build script for plugin-base
+
+use std::path::Path;
+
+fn main() {
+    // embed the verifier key only if one has been generated
+    let key = Path::new(env!("CARGO_MANIFEST_DIR")).join("../public-key.pem");
+    println!("cargo:rerun-if-changed={}", key.display());
+    println!("cargo:rustc-check-cfg=cfg(embedded_verifier_key)");
+    if key.is_file() {
+        println!("cargo:rustc-cfg=embedded_verifier_key");
+    } else {
+        println!("cargo:warning=public-key.pem not found, plugins can only be loaded with a runtime verifier key");
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{Error, Result};
+use plugin_defs::Package;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Entry of a bundle listing the packages it is expected to contain.
+pub(crate) const MANIFEST_NAME: &str = "manifest.json";
+
+/// Largest file read from a bundle, whatever size its header claims.
+pub(crate) const MAX_ENTRY_SIZE: u64 = 256 << 20;
+
+/// Largest total size of the files read from a bundle.
+pub(crate) const MAX_BUNDLE_SIZE: u64 = 1 << 30;
+
+/// Bundle manifest, mapping each package entry to the hex digest of its library:
+///
+/// ```json
+/// { "packages": { "spider.cdp": "9f3c..." } }
+/// ```
+#[derive(Deserialize)]
+pub(crate) struct BundleManifest {
+    packages: BTreeMap<String, String>,
+}
+
+impl BundleManifest {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(|e| Error::InvalidBundle(e.to_string()))
+    }
+
+    /// Check that the bundle holds exactly the listed packages, with the listed digests.
+    pub fn check(&self, packages: &[(String, Package)]) -> Result<()> {
+        for entry in self.packages.keys() {
+            if !packages.iter().any(|(name, _)| name == entry) {
+                return Err(Error::MissingBundleEntry(entry.clone()));
+            }
+        }
+        for (entry, package) in packages {
+            let Some(digest) = self.packages.get(entry) else {
+                return Err(Error::UnexpectedBundleEntry(entry.clone()));
+            };
+            if !digest.eq_ignore_ascii_case(&package.metadata.digest) {
+                return Err(Error::BundleDigestMismatch(entry.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read every file of an archive into memory, keyed by its path in the archive.
+/// Archives ending in `.zip` are read as zip, anything else as tar.
+///
+/// A file larger than [`MAX_ENTRY_SIZE`], or files adding up to more than
+/// [`MAX_BUNDLE_SIZE`], are reported as [`Error::InvalidBundle`].
+pub(crate) fn read_archive(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    read_archive_limited(path, MAX_ENTRY_SIZE, MAX_BUNDLE_SIZE)
+}
+
+fn read_archive_limited(
+    path: &Path,
+    entry_limit: u64,
+    total_limit: u64,
+) -> Result<BTreeMap<String, Vec<u8>>> {
+    let file = File::open(path)?;
+    let mut entries = BTreeMap::new();
+    let mut remaining = total_limit;
+    if path.extension().is_some_and(|ext| ext == "zip") {
+        let mut archive =
+            zip::ZipArchive::new(file).map_err(|e| Error::InvalidBundle(e.to_string()))?;
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .map_err(|e| Error::InvalidBundle(e.to_string()))?;
+            if !entry.is_file() {
+                continue;
+            }
+            let name = entry.name().to_string();
+            let content = read_entry(&mut entry, &name, entry_limit, &mut remaining)?;
+            entries.insert(name, content);
+        }
+    } else {
+        let mut archive = tar::Archive::new(file);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let content = read_entry(&mut entry, &name, entry_limit, &mut remaining)?;
+            entries.insert(name, content);
+        }
+    }
+    Ok(entries)
+}
+
+/// Read an entry, never more than `entry_limit` or the `remaining` bytes of the bundle.
+fn read_entry<R: Read>(
+    entry: R,
+    name: &str,
+    entry_limit: u64,
+    remaining: &mut u64,
+) -> Result<Vec<u8>> {
+    let limit = entry_limit.min(*remaining);
+    let mut content = Vec::new();
+    entry.take(limit + 1).read_to_end(&mut content)?;
+    let len = content.len() as u64;
+    if len > entry_limit {
+        return Err(Error::InvalidBundle(format!(
+            "{name} is larger than {entry_limit} bytes"
+        )));
+    }
+    if len > limit {
+        return Err(Error::InvalidBundle(format!(
+            "bundle exceeds its size limit at {name}"
+        )));
+    }
+    *remaining -= len;
+    Ok(content)
+}
+
+#[cfg(test)]
+use crate::loader::package;
+
+#[test]
+fn test_manifest_check() {
+    let packages = vec![package("spider", &[])];
+    let digest = packages[0].1.metadata.digest.clone();
+
+    let manifest = BundleManifest::parse(
+        format!(r#"{{ "packages": {{ "spider.cdp": "{digest}" }} }}"#).as_bytes(),
+    )
+    .unwrap();
+    manifest.check(&packages).unwrap();
+
+    let manifest = BundleManifest::parse(
+        format!(r#"{{ "packages": {{ "spider.cdp": "{digest}", "other.cdp": "00" }} }}"#)
+            .as_bytes(),
+    )
+    .unwrap();
+    assert!(matches!(
+        manifest.check(&packages),
+        Err(Error::MissingBundleEntry(entry)) if entry == "other.cdp"
+    ));
+
+    let manifest = BundleManifest::parse(br#"{ "packages": { "spider.cdp": "00" } }"#).unwrap();
+    assert!(matches!(
+        manifest.check(&packages),
+        Err(Error::BundleDigestMismatch(_))
+    ));
+}
+
+#[test]
+fn test_read_archive_limits() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.tar");
+    let mut builder = tar::Builder::new(File::create(&path).unwrap());
+    for name in ["a.cdp", "b.cdp"] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(10);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, [0u8; 10].as_slice())
+            .unwrap();
+    }
+    builder.finish().unwrap();
+    drop(builder);
+
+    assert_eq!(read_archive_limited(&path, 10, 20).unwrap().len(), 2);
+    assert!(matches!(
+        read_archive_limited(&path, 9, 20),
+        Err(Error::InvalidBundle(e)) if e.contains("a.cdp")
+    ));
+    assert!(matches!(
+        read_archive_limited(&path, 10, 19),
+        Err(Error::InvalidBundle(e)) if e.contains("b.cdp")
+    ));
+}
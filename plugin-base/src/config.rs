@@ -0,0 +1,40 @@
+//! Helpers for plugins parsing the config handed to their constructor.
+
+use crate::log::*;
+use crate::PluginError;
+use serde::de::DeserializeOwned;
+
+/// Parse the JSON config passed to a plugin constructor.
+///
+/// A missing config and one that doesn't parse both fail with [`PluginError::InvalidConfig`],
+/// the parse error is logged through the plugin logger.
+pub fn parse_json<T: DeserializeOwned>(config: Option<String>) -> Result<T, PluginError> {
+    let Some(config) = config else {
+        warn!("plugin config is missing");
+        return Err(PluginError::InvalidConfig);
+    };
+    serde_json::from_str(&config).map_err(|e| {
+        warn!("plugin config is invalid: {}", e);
+        PluginError::InvalidConfig
+    })
+}
+
+#[test]
+fn test_parse_json() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        start_url: String,
+    }
+
+    let config: Config =
+        parse_json(Some(r#"{"start_url": "https://example.com"}"#.to_string())).unwrap();
+    assert_eq!(config.start_url, "https://example.com");
+    assert!(matches!(
+        parse_json::<Config>(Some("{}".to_string())),
+        Err(PluginError::InvalidConfig)
+    ));
+    assert!(matches!(
+        parse_json::<Config>(None),
+        Err(PluginError::InvalidConfig)
+    ));
+}
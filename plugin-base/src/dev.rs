@@ -0,0 +1,31 @@
+//! Loading unpackaged libraries during plugin development, see [`PluginManager::dev_mode`].
+//!
+//! [`PluginManager::dev_mode`]: crate::PluginManager::dev_mode
+
+use crate::log::*;
+use crate::{Error, Result};
+use plugin_defs::{LibraryFormat, Package, PackageMetadata};
+use std::fs;
+use std::path::Path;
+
+/// Whether a file is a native library rather than a package.
+pub(crate) fn is_raw_library(content: &[u8]) -> bool {
+    LibraryFormat::detect(content).is_some()
+}
+
+/// Wrap a raw library into an unsigned package, with the metadata found next to it.
+pub(crate) fn package_raw_library(path: &Path, library: Vec<u8>) -> Result<Package> {
+    warn!(
+        "DEV MODE: loading {:?} WITHOUT signature verification, never use this in production",
+        path
+    );
+    let candidates = PackageMetadata::sidecar_paths(path);
+    let Some(sidecar) = candidates.iter().find(|p| p.is_file()) else {
+        return Err(Error::DevMetadata(format!(
+            "no metadata for {path:?}, tried {candidates:?}"
+        )));
+    };
+    let metadata: PackageMetadata = serde_json::from_slice(&fs::read(sidecar)?)
+        .map_err(|e| Error::DevMetadata(format!("{sidecar:?}: {e}")))?;
+    Ok(Package::new(metadata, library))
+}
@@ -1,34 +1,96 @@
 #![allow(clippy::let_unit_value)]
 
 use abi_stable::sabi_trait;
-use abi_stable::std_types::{RNone, ROption, RResult, RStr, RString};
+use abi_stable::std_types::{
+    RDuration, RErr, RNone, ROk, ROption, RResult, RSlice, RSome, RStr, RString, RVec,
+};
 use abi_stable::StableAbi;
 use ed25519_dalek::VerifyingKey;
 use konst::{primitive::parse_u64, unwrap_ctx};
 use libloading::{Library, Symbol};
 use once_cell::sync::Lazy;
-use plugin_defs::Package;
+use plugin_defs::{
+    DependencySpec, Dictionary, Digest, LibraryFormat, LockedPlugin, Package, PackageMetadata,
+    PluginLock,
+};
 use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
 use std::io;
-use std::ops::Deref;
-use std::path::Path;
+use std::panic::Location;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
 
 pub use abi_stable;
 
 pub use log::{self, debug, error, info, trace, warn};
 pub use semver;
+pub use source::{DirectorySource, PackageSource};
+pub use symbols::SymbolPolicy;
 
+#[cfg(unix)]
+pub use libloading::os::unix::{RTLD_GLOBAL, RTLD_LAZY, RTLD_LOCAL, RTLD_NOW};
+
+/// `dlopen` flags plugin libraries are opened with unless set with
+/// [`PluginManager::with_dlopen_flags`]: symbols stay private to each plugin, and
+/// unresolved symbols fail the load instead of the first call.
+#[cfg(unix)]
+pub const DEFAULT_DLOPEN_FLAGS: i32 = RTLD_LOCAL | RTLD_NOW;
+/// There are no `dlopen` flags on Windows.
+#[cfg(not(unix))]
+pub const DEFAULT_DLOPEN_FLAGS: i32 = 0;
+
+pub mod config;
 pub mod logger;
+#[doc(hidden)]
+pub mod panic;
+pub mod source;
+pub mod symbols;
 
+#[cfg(feature = "bundle")]
+mod bundle;
+#[cfg(feature = "dev-unsafe")]
+mod dev;
+mod loader;
 mod utils;
 
+use crate::loader::{LoaderConfig, OpenedLibrary};
 use crate::logger::{log_callback, LogCallback};
 
-static VERIFIER_KEY: Lazy<VerifyingKey> = Lazy::new(|| {
+/// The verifier key embedded at build time, if `public-key.pem` existed.
+#[cfg(embedded_verifier_key)]
+static VERIFIER_KEY: Lazy<Option<VerifyingKey>> = Lazy::new(|| {
     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/../public-key.pem"));
-    let key = pem::parse(key).unwrap().contents;
-    VerifyingKey::from_bytes(&key.try_into().unwrap()).unwrap()
+    Some(load_verifying_key_pem(key).expect("embedded public-key.pem is invalid"))
 });
+#[cfg(not(embedded_verifier_key))]
+static VERIFIER_KEY: Lazy<Option<VerifyingKey>> = Lazy::new(|| None);
+
+/// Parse an ed25519 verifying key from the PEM written by `plugin-pack gen-keypair`.
+pub fn load_verifying_key_pem(bytes: &[u8]) -> Result<VerifyingKey> {
+    let key = pem::parse(bytes)
+        .map_err(|e| Error::InvalidVerifierKey(e.to_string()))?
+        .contents;
+    let key: [u8; 32] = key.try_into().map_err(|key: Vec<u8>| {
+        Error::InvalidVerifierKey(format!("expect 32 bytes, got {}", key.len()))
+    })?;
+    VerifyingKey::from_bytes(&key).map_err(|e| Error::InvalidVerifierKey(e.to_string()))
+}
+
+/// Fingerprint of the verifier key embedded at build time, as printed by
+/// `plugin-pack fingerprint`. `None` if no key was embedded.
+pub fn verifier_fingerprint() -> Option<String> {
+    VERIFIER_KEY
+        .as_ref()
+        .map(|key| plugin_defs::fingerprint(key.as_bytes()))
+}
 
 pub const API_VERSION: Version = Version::new(
     unwrap_ctx!(parse_u64(env!("CARGO_PKG_VERSION_MAJOR"))),
@@ -36,31 +98,343 @@ pub const API_VERSION: Version = Version::new(
     unwrap_ctx!(parse_u64(env!("CARGO_PKG_VERSION_PATCH"))),
 );
 
+/// How the library is brought from a verified package onto disk and into the process.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum LoadStrategy {
+    /// Release the library to a temp file, lock it, and re-hash the on-disk bytes
+    /// before loading. Guards against the file being swapped between release and load.
+    /// Only the library is re-hashed, the resources released next to it are not.
+    #[default]
+    ReleaseRecheck,
+    /// Release the library to a temp file and load it directly, trusting the
+    /// digest already verified on the in-memory package.
+    TrustInMemory,
+}
+
+/// Steps of a load reported to a [`ProgressCallback`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LoadPhase {
+    /// reading, decompressing and verifying the package
+    Decompressing,
+    /// releasing the library to a temp file
+    WritingTemp,
+    /// re-hashing the released library
+    Hashing,
+    /// opening the library
+    Linking,
+}
+
+/// Receives the load phase along with bytes done and bytes total.
+pub type ProgressCallback = fn(LoadPhase, u64, u64);
+
+/// Receives the name of a plugin skipped for an unmet version requirement, the
+/// requirement and the version it was checked against, if the host declared one.
+pub type UnmetRequirementCallback = fn(&str, &str, Option<&Version>);
+
+/// Where a plugin was loaded from, recorded for audit.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum PluginSource {
+    /// a package file on disk
+    File(PathBuf),
+    /// a package already held in memory
+    Bytes,
+    /// a package compiled into the host, see `PluginManager::load_embedded`
+    Embedded,
+    /// an in-process plugin, see `PluginManager::load_mock`
+    Mock,
+    /// an unpackaged library loaded without verification, see `PluginManager::dev_mode`
+    Unsigned(PathBuf),
+}
+
+/// Host policy deciding whether a verified package may be loaded, see
+/// [`PluginManager::with_load_filter`].
+pub type LoadFilter =
+    Arc<dyn Fn(&PackageMetadata) -> std::result::Result<(), String> + Send + Sync>;
+
+/// Time spent in each step of a load, see [`LoadReport`].
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LoadTimings {
+    /// decompressing the package and verifying its signature and digest
+    pub decompress: Duration,
+    pub write_temp: Duration,
+    /// re-hashing the released library, zero with [`LoadStrategy::TrustInMemory`]
+    pub hash: Duration,
+    pub dlopen: Duration,
+    /// calling the plugin constructor and admitting the plugin
+    pub construct: Duration,
+}
+
+/// Per-call options of [`PluginManager::load_plugin_opts`].
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    /// load the package even if a plugin with the same digest is already loaded,
+    /// replacing it
+    pub force: bool,
+    /// config handed to the plugin constructor
+    pub config: Option<String>,
+}
+
+/// Summary of a finished load, serializable for logs and metrics.
+#[derive(Clone, Debug, Serialize)]
+pub struct LoadReport {
+    pub name: String,
+    pub version: Version,
+    pub digest: String,
+    pub source: PluginSource,
+    pub timings: LoadTimings,
+}
+
+/// What [`PluginManager::validate_plugin`] learned about a package.
+#[derive(Clone, Debug)]
+pub struct PluginInfo {
+    pub metadata: PackageMetadata,
+    /// whether the library declares a config schema via `declare_plugin!`
+    pub has_config_schema: bool,
+    pub source: PluginSource,
+}
+
+/// Canonical form of a plugin name used to identify it in the manager.
+///
+/// Names are trimmed and compared case-insensitively, so `Spider` and ` spider `
+/// refer to the same plugin.
+pub fn canonical_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Identity of a loaded plugin: the [`PackageMetadata::id`] of its package, or its
+/// [`canonical_name`] for packages without one and mocks.
+///
+/// Plugins of different vendors may share a name as long as their ids differ, names
+/// are only a label then.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct PluginId(String);
+
+impl PluginId {
+    pub fn new(id: impl Into<String>) -> Self {
+        PluginId(id.into())
+    }
+
+    fn of(metadata: Option<&PackageMetadata>, key: &str) -> Self {
+        match metadata.and_then(|metadata| metadata.id.as_deref()) {
+            Some(id) => PluginId::new(id),
+            None => PluginId::new(key),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for PluginId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// How a loaded plugin is looked up: by [`PluginId`], or by name, see [`canonical_name`].
+///
+/// A name only resolves while a single loaded plugin has it, plugins sharing a name are
+/// looked up by id, see [`PluginManager::plugin_ids`].
+#[derive(Copy, Clone, Debug)]
+pub enum PluginRef<'a> {
+    Id(&'a PluginId),
+    Name(&'a str),
+}
+
+impl<'a> From<&'a PluginId> for PluginRef<'a> {
+    fn from(id: &'a PluginId) -> Self {
+        PluginRef::Id(id)
+    }
+}
+
+impl<'a> From<&'a str> for PluginRef<'a> {
+    fn from(name: &'a str) -> Self {
+        PluginRef::Name(name)
+    }
+}
+
+impl<'a> From<&'a String> for PluginRef<'a> {
+    fn from(name: &'a String) -> Self {
+        PluginRef::Name(name)
+    }
+}
+
+impl Display for PluginRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginRef::Id(id) => id.fmt(f),
+            PluginRef::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Whether the plugin with canonical name `key` and id `id` satisfies `dep`: by id if the
+/// dependency names one, by name otherwise. The version is not checked.
+pub(crate) fn satisfies(dep: &DependencySpec, key: &str, id: &PluginId) -> bool {
+    match &dep.id {
+        Some(dep_id) => id.as_str() == dep_id,
+        None => canonical_name(&dep.name) == key,
+    }
+}
+
+/// What loading a verified package does to the loaded plugins.
+enum Admission {
+    /// a plugin with the same digest is loaded, at this index
+    Loaded(usize),
+    /// nothing loaded has the digest
+    New,
+    /// replaces the plugin with the same digest, taken out from this index
+    Replace(usize, Box<LoadedPlugin>),
+}
+
+struct LoadedPlugin {
+    /// see [`canonical_name`]
+    key: String,
+    id: PluginId,
+    plugin: Box<dyn Plugin>,
+    source: PluginSource,
+    /// metadata of the package it was loaded from, `None` for mocks
+    metadata: Option<PackageMetadata>,
+    /// index into `PluginManager::loaded_libraries`, `None` for mocks
+    library: Option<usize>,
+    /// where the package resources were released, dropped after the plugin
+    resources: Option<TempDir>,
+}
+
+impl LoadedPlugin {
+    fn dependencies(&self) -> &[DependencySpec] {
+        self.metadata
+            .as_ref()
+            .map_or(&[], |metadata| metadata.dependencies.as_slice())
+    }
+
+    fn depends_on(&self, other: &LoadedPlugin) -> bool {
+        self.dependencies()
+            .iter()
+            .any(|dep| satisfies(dep, &other.key, &other.id))
+    }
+
+    /// see [`PackageMetadata::priority`], mocks have the default 0
+    fn priority(&self) -> i32 {
+        self.metadata
+            .as_ref()
+            .map_or(0, |metadata| metadata.priority)
+    }
+}
+
 pub struct PluginManager {
-    plugins: Vec<Box<dyn Plugin>>,
-    loaded_libraries: Vec<Library>,
+    plugins: Vec<LoadedPlugin>,
+    /// `None` once the library has been closed or leaked
+    loaded_libraries: Vec<Option<Library>>,
+    options: LoaderConfig,
+    /// extension [`PluginManager::load_plugin_dir`] filters on, `None` accepts every file
+    package_extension: Option<String>,
+    /// version of the host application, independent of [`API_VERSION`]
+    host_version: Option<Version>,
+    unmet_requirement: Option<UnmetRequirementCallback>,
+    /// how many plugins may be loaded at once, `None` for no limit
+    max_plugins: Option<usize>,
+    /// where [`PluginManager::load_plugin_by_name`] fetches packages from
+    package_source: Option<Arc<dyn PackageSource>>,
+    #[cfg(feature = "dev-unsafe")]
+    dev_mode: bool,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("unable to load the plugin package: {0}")]
     InvalidPackage(#[from] plugin_defs::Error),
-    #[error("unable to load the plugin lib: {0}")]
-    LibraryLoad(libloading::Error),
+    #[error("unable to load the library of plugin {name}, released to {path:?}: {source}")]
+    LibraryLoad {
+        name: String,
+        /// where the library was released to, the temp dir is gone by the time this is seen
+        path: PathBuf,
+        source: libloading::Error,
+    },
     #[error("cannot found symbol {0}")]
     MissingSymbol(String),
+    #[error("plugin {name} exports the denied symbol {symbol}")]
+    DeniedSymbol { name: String, symbol: String },
+    #[error("cannot read the exports of plugin {name}: {reason}")]
+    UnreadableExports { name: String, reason: String },
+    #[error("plugin {0} is not loaded")]
+    UnknownPlugin(String),
+    #[error("several loaded plugins are named {0}, look them up by id")]
+    AmbiguousName(String),
+    #[error("no package source to load plugins by name from")]
+    NoPackageSource,
+    #[error("no package of plugin {name} matches {req}")]
+    PackageNotFound { name: String, req: String },
     #[error("plugin {name} used an invalid version req {req}")]
     InvalidVersionReq { name: String, req: String },
-    #[error("plugin {name} used an unmet version req {req}")]
-    UnmetRequirement { name: String, req: String },
+    #[error(
+        "plugin {name} requires {req}, but the version is {}",
+        fmt_actual_version(actual)
+    )]
+    UnmetRequirement {
+        name: String,
+        req: String,
+        /// the version checked against, `None` if the host declared none
+        actual: Option<Version>,
+    },
+    #[error("plugin name {0:?} is empty")]
+    InvalidPluginName(String),
+    #[error("plugin {0} is already loaded")]
+    DuplicatePlugin(String),
+    #[error("package is named {metadata}, but the plugin calls itself {runtime}")]
+    NameMismatch { metadata: String, runtime: String },
     #[error("plugin initialization failed: {0}")]
     PluginInitialization(PluginError),
+    #[error("cannot load more than {limit} plugins, {loaded} are loaded")]
+    CapacityExceeded { limit: usize, loaded: usize },
+    #[error("plugin {name} was not constructed within {timeout:?}")]
+    LoadTimeout { name: String, timeout: Duration },
     #[error("failed to lock library file: {0}")]
     LockFile(io::Error),
+    #[error("invalid verifier key: {0}")]
+    InvalidVerifierKey(String),
+    #[error("no verifier key embedded at build time or provided at runtime")]
+    NoVerifierKey,
+    #[error("plugin build {0} has been revoked")]
+    Revoked(Digest),
+    #[error("plugin {name} is pinned to build {expected}, but the package is build {actual}")]
+    FingerprintMismatch {
+        name: String,
+        expected: Digest,
+        actual: Digest,
+    },
+    #[cfg(feature = "dev-unsafe")]
+    #[error("dev mode cannot read the library metadata: {0}")]
+    DevMetadata(String),
+    #[error("no package matches the locked build of {0}")]
+    LockMismatch(String),
+    #[error("plugin rejected by the host: {0}")]
+    Rejected(String),
+    #[error("plugin was built for {expected}, but this host is {actual}")]
+    TargetMismatch { expected: String, actual: String },
+    #[error("plugin library is {detected}, but this host loads {host}")]
+    WrongPlatform {
+        detected: LibraryFormat,
+        host: LibraryFormat,
+    },
+    #[error("invalid bundle: {0}")]
+    InvalidBundle(String),
+    #[error("bundle manifest lists {0} but the bundle does not contain it")]
+    MissingBundleEntry(String),
+    #[error("bundle entry {0} is not listed in the manifest")]
+    UnexpectedBundleEntry(String),
+    #[error("bundle entry {0} does not match the digest in the manifest")]
+    BundleDigestMismatch(String),
+    #[error("plugin {0} is part of a dependency cycle")]
+    DependencyCycle(String),
     #[error("another entity is tampering current program")]
     Tampered,
     #[error("generic io error: {0}")]
     Io(#[from] io::Error),
+    #[cfg(feature = "tokio")]
+    #[error("blocking load task failed: {0}")]
+    Join(tokio::task::JoinError),
 }
 
 #[repr(u8)]
@@ -70,27 +444,216 @@ pub enum PluginError {
     InvalidConfig,
     #[error("cannot set logger")]
     SetLogger,
-    #[error("{0}")]
-    Custom(RString),
+    #[error("{message}{}", fmt_source_location(source_location))]
+    Custom {
+        message: RString,
+        /// where in the plugin the error was raised, if known
+        source_location: ROption<RString>,
+    },
+    // `Custom` became a struct variant in 0.2, which changed the layout: plugins built
+    // against 0.1 fail the API version check before they are constructed. Variants are
+    // appended to keep the discriminants within a release.
+    #[error("dependency {0} is unavailable")]
+    DependencyUnavailable(RString),
+    #[error("resource {0} not found")]
+    ResourceNotFound(RString),
+    #[error("permission denied")]
+    PermissionDenied,
+    #[error("timed out")]
+    Timeout,
+}
+
+fn fmt_actual_version(actual: &Option<Version>) -> String {
+    match actual {
+        Some(version) => version.to_string(),
+        None => "undeclared".to_string(),
+    }
+}
+
+fn fmt_source_location(location: &ROption<RString>) -> String {
+    match location {
+        RSome(location) => format!(" (at {location})"),
+        RNone => String::new(),
+    }
+}
+
+impl PluginError {
+    /// Wrap anything displayable into [`PluginError::Custom`], recording the caller location,
+    /// e.g. `serde_json::from_str(..).map_err(PluginError::custom)?`.
+    #[track_caller]
+    pub fn custom(message: impl Display) -> Self {
+        PluginError::Custom {
+            message: RString::from(message.to_string()),
+            source_location: RSome(RString::from(Location::caller().to_string())),
+        }
+    }
+}
+
+// A blanket `impl<E: Error> From<E>` would conflict with `From<PluginError>`,
+// so only the common cases are converted implicitly.
+impl From<io::Error> for PluginError {
+    #[track_caller]
+    fn from(error: io::Error) -> Self {
+        PluginError::custom(error)
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for PluginError {
+    #[track_caller]
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        PluginError::custom(error)
+    }
+}
+
+impl From<String> for PluginError {
+    #[track_caller]
+    fn from(message: String) -> Self {
+        PluginError::custom(message)
+    }
+}
+
+impl From<&str> for PluginError {
+    #[track_caller]
+    fn from(message: &str) -> Self {
+        PluginError::custom(message)
+    }
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-/// Plugin trait
-#[sabi_trait]
-pub trait Plugin: Send + Sync {
-    fn name(&self) -> RStr<'static>;
-    /// get used API_VERSION
-    fn api_version_require(&self) -> RStr<'static> {
-        RStr::from(concat!("^", env!("CARGO_PKG_VERSION")))
+// abi_stable 0.11 expands the trait object impls of `#[sabi_trait]` inside a const,
+// which the lint flags as non-local
+#[allow(non_local_definitions)]
+mod plugin_trait {
+    use super::*;
+
+    /// Plugin trait
+    #[sabi_trait]
+    pub trait Plugin: Send + Sync {
+        fn name(&self) -> RStr<'static>;
+        /// get used API_VERSION
+        fn api_version_require(&self) -> RStr<'static> {
+            RStr::from(concat!("^", env!("CARGO_PKG_VERSION")))
+        }
+        /// required host version, checked against [`PluginManager::new_with_host_version`]
+        fn host_version_require(&self) -> ROption<RStr<'static>> {
+            RNone
+        }
+        /// on load callback
+        fn on_plugin_load(&self) {
+            info!("plugin loaded")
+        }
+        /// on unload callback, errors are returned by [`PluginManager::shutdown`]
+        fn on_plugin_unload(&self) -> RResult<(), PluginError> {
+            info!("plugin unloaded");
+            ROk(())
+        }
+        /// capability identifiers this plugin implements, e.g. `comet.spider.v1`
+        fn interfaces(&self) -> RVec<RString> {
+            RVec::new()
+        }
+        /// handle a request, both payload and response are opaque serialized bytes
+        fn invoke(
+            &self,
+            method: RStr<'_>,
+            payload: RSlice<'_, u8>,
+        ) -> RResult<RVec<u8>, PluginError> {
+            let _ = (method, payload);
+            RErr(PluginError::Custom {
+                message: RString::from("unsupported method"),
+                source_location: RNone,
+            })
+        }
+        /// react to a host event, see [`PluginManager::broadcast`]; ignored by default
+        fn on_event(&self, event: RStr<'_>, payload: RSlice<'_, u8>) -> RResult<(), PluginError> {
+            let _ = (event, payload);
+            ROk(())
+        }
+        /// apply a new config in place, see [`PluginManager::reconfigure`]; unsupported by default
+        fn on_reconfigure(&self, config: ROption<RString>) -> RResult<(), PluginError> {
+            let _ = config;
+            RErr(PluginError::Custom {
+                message: RString::from("reconfigure unsupported"),
+                source_location: RNone,
+            })
+        }
+        /// report whether the plugin still works, see [`PluginManager::health_check_all`]
+        fn health_check(&self) -> RResult<(), PluginError> {
+            ROk(())
+        }
+        /// how long `on_plugin_unload` may run before the manager gives up waiting on it,
+        /// see [`PluginManager::shutdown`]
+        fn preferred_shutdown(&self) -> RDuration {
+            RDuration::from_secs(1)
+        }
+    }
+}
+pub use plugin_trait::*;
+
+// both sides are compiled by rustc, the C ABI only has to match `declare_plugin!`
+#[allow(improper_ctypes_definitions)]
+type PluginCreate =
+    unsafe extern "C" fn(ROption<RString>, LogCallback) -> RResult<*mut dyn Plugin, PluginError>;
+
+/// Call a plugin constructor on a worker thread, `None` if it didn't return within `timeout`.
+unsafe fn construct_with_timeout(
+    constructor: PluginCreate,
+    config: ROption<RString>,
+    timeout: Duration,
+) -> Result<Option<RResult<*mut dyn Plugin, PluginError>>> {
+    struct Created(RResult<*mut dyn Plugin, PluginError>);
+    // the plugin is only used by the loading thread once it is handed over
+    unsafe impl Send for Created {}
+
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("plugin-constructor".to_string())
+        .spawn(move || {
+            // after a timeout nobody receives, the plugin is leaked with its library
+            let _ = sender.send(Created(unsafe { constructor(config, log_callback) }));
+        })?;
+    match receiver.recv_timeout(timeout) {
+        Ok(Created(created)) => Ok(Some(created)),
+        Err(RecvTimeoutError::Timeout) => Ok(None),
+        Err(RecvTimeoutError::Disconnected) => Ok(Some(RErr(PluginError::custom(
+            "plugin constructor thread exited without returning",
+        )))),
+    }
+}
+
+/// Run the unload hook of a plugin on a worker thread, `None` if it didn't return within
+/// `timeout`. The plugin is dropped on the calling thread unless the hook timed out.
+fn unload_with_timeout(
+    plugin: Box<dyn Plugin>,
+    timeout: Duration,
+) -> Option<std::result::Result<(), PluginError>> {
+    let (sender, receiver) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name("plugin-unload".to_string())
+        .spawn(move || {
+            let result = plugin.on_plugin_unload().into_result();
+            // after a timeout nobody receives, the plugin is leaked with its library
+            if let Err(mpsc::SendError((plugin, _))) = sender.send((plugin, result)) {
+                std::mem::forget(plugin);
+            }
+        });
+    if let Err(e) = spawned {
+        return Some(Err(PluginError::custom(format!(
+            "cannot spawn the unload thread: {e}"
+        ))));
     }
-    /// on load callback
-    fn on_plugin_load(&self) {
-        info!("plugin loaded")
+    match receiver.recv_timeout(timeout) {
+        Ok((_, result)) => Some(result),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => Some(Err(PluginError::custom(
+            "plugin unload thread exited without returning",
+        ))),
     }
-    /// on unload callback
-    fn on_plugin_unload(&self) {
-        info!("plugin unloaded")
+}
+
+impl Drop for PluginManager {
+    fn drop(&mut self) {
+        self.unload_all();
     }
 }
 
@@ -102,96 +665,2162 @@ impl Default for PluginManager {
 
 impl PluginManager {
     pub fn new() -> PluginManager {
+        Self::with_strategy(LoadStrategy::default())
+    }
+
+    pub fn with_strategy(strategy: LoadStrategy) -> PluginManager {
         PluginManager {
             plugins: Vec::new(),
             loaded_libraries: Vec::new(),
+            options: LoaderConfig {
+                strategy,
+                verifier: *VERIFIER_KEY,
+                progress: None,
+                revoked: Arc::new(HashSet::new()),
+                pinned: Arc::new(HashMap::new()),
+                dictionaries: Arc::new(Vec::new()),
+                filter: None,
+                dlopen_flags: DEFAULT_DLOPEN_FLAGS,
+                load_timeout: None,
+                symbol_policy: None,
+            },
+            package_extension: Some("cdp".to_string()),
+            host_version: None,
+            unmet_requirement: None,
+            max_plugins: None,
+            package_source: None,
+            #[cfg(feature = "dev-unsafe")]
+            dev_mode: false,
+        }
+    }
+
+    /// Create a manager for a host of the given version, plugins declaring a
+    /// [`Plugin::host_version_require`] are checked against it.
+    pub fn new_with_host_version(version: Version) -> PluginManager {
+        let mut manager = Self::new();
+        manager.host_version = Some(version);
+        manager
+    }
+
+    /// Only load files with this extension from a directory, `cdp` by default.
+    pub fn with_extension(mut self, extension: impl Into<String>) -> Self {
+        self.package_extension = Some(extension.into());
+        self
+    }
+
+    /// Try every file in a directory, skipping the ones that are not packages.
+    /// Useful when packages are stored without extension, e.g. by digest.
+    pub fn with_any_extension(mut self) -> Self {
+        self.package_extension = None;
+        self
+    }
+
+    /// Verify packages with the given key instead of the embedded one.
+    pub fn with_verifier(mut self, key: VerifyingKey) -> Self {
+        self.options.verifier = Some(key);
+        self
+    }
+
+    /// Fingerprint of the key packages are verified with, embedded or set with
+    /// [`PluginManager::with_verifier`].
+    pub fn verifier_fingerprint(&self) -> Option<String> {
+        self.options
+            .verifier
+            .map(|key| plugin_defs::fingerprint(key.as_bytes()))
+    }
+
+    /// **Unsafe for production.** Let [`PluginManager::load_plugin`] and
+    /// [`PluginManager::load_plugin_opts`] take a raw `.so`/`.dylib`/`.dll` instead of a
+    /// package, skipping the package import: no signature, digest, revocation, pin, target
+    /// or filter check is made. The metadata is read from the file `plugin-pack` would use,
+    /// `<libname>.metadata.json` or `metadata.json` next to the library. The API and host
+    /// version checks and the constructor symbol lookup still apply.
+    ///
+    /// Only compiled with the `dev-unsafe` feature, every such load logs a warning.
+    #[cfg(feature = "dev-unsafe")]
+    pub fn dev_mode(mut self, enabled: bool) -> Self {
+        self.dev_mode = enabled;
+        self
+    }
+
+    /// Tell `callback` about every plugin refused for an unmet API or host version
+    /// requirement, e.g. to warn about each one skipped by [`PluginManager::load_plugin_dir`].
+    /// The load still fails with [`Error::UnmetRequirement`].
+    pub fn with_unmet_requirement_callback(mut self, callback: UnmetRequirementCallback) -> Self {
+        self.unmet_requirement = Some(callback);
+        self
+    }
+
+    /// Report the progress of each load to `callback`.
+    pub fn with_progress(mut self, callback: ProgressCallback) -> Self {
+        self.options.progress = Some(callback);
+        self
+    }
+
+    /// Refuse packages whose library digest is in `set`, even if they are validly signed.
+    pub fn with_revocation(mut self, set: HashSet<Digest>) -> Self {
+        self.options.revoked = Arc::new(set);
+        self
+    }
+
+    /// Open plugin libraries with these `dlopen` flags, e.g. `RTLD_GLOBAL | RTLD_NOW` for a
+    /// plugin whose own dependencies need its symbols. Has no effect on Windows.
+    pub fn with_dlopen_flags(mut self, flags: i32) -> Self {
+        self.options.dlopen_flags = flags;
+        self
+    }
+
+    /// Check the symbols exported by plugin libraries against `policy` before they are
+    /// opened, failing with [`Error::MissingSymbol`] or [`Error::DeniedSymbol`].
+    pub fn with_symbol_policy(mut self, policy: SymbolPolicy) -> Self {
+        self.options.symbol_policy = Some(policy);
+        self
+    }
+
+    /// Resolve the symbols of plugin libraries on first call (`RTLD_LAZY`) instead of at
+    /// load (`RTLD_NOW`, the default), for plugins that legitimately reference symbols that
+    /// may be missing. Keeps the other [`PluginManager::with_dlopen_flags`]. Has no effect
+    /// on Windows.
+    pub fn with_lazy_binding(mut self, lazy: bool) -> Self {
+        #[cfg(unix)]
+        {
+            let binding = if lazy { RTLD_LAZY } else { RTLD_NOW };
+            self.options.dlopen_flags =
+                self.options.dlopen_flags & !(RTLD_LAZY | RTLD_NOW) | binding;
+        }
+        #[cfg(not(unix))]
+        let _ = lazy;
+        self
+    }
+
+    /// Fetch packages from `source` in [`PluginManager::load_plugin_by_name`].
+    pub fn with_package_source(mut self, source: impl PackageSource + 'static) -> Self {
+        self.package_source = Some(Arc::new(source));
+        self
+    }
+
+    /// Refuse to load more than `limit` plugins with [`Error::CapacityExceeded`], checked
+    /// before the library of a new plugin is opened. There is no limit by default.
+    pub fn with_max_plugins(mut self, limit: usize) -> Self {
+        self.max_plugins = Some(limit);
+        self
+    }
+
+    /// Give up on a plugin constructor that doesn't return within `timeout`, failing
+    /// the load with [`Error::LoadTimeout`]. There is no timeout by default.
+    ///
+    /// The constructor then runs on a worker thread. Foreign code can't be interrupted,
+    /// so on timeout the thread is abandoned and its library and resources are leaked:
+    /// they stay mapped for as long as the process runs.
+    pub fn with_load_timeout(mut self, timeout: Duration) -> Self {
+        self.options.load_timeout = Some(timeout);
+        self
+    }
+
+    /// Pin the library digest expected for each plugin name, refusing a validly signed
+    /// package of a pinned plugin whose digest differs with [`Error::FingerprintMismatch`].
+    /// Plugins without a pin are only checked against the signature.
+    pub fn with_pinned_fingerprints(mut self, pins: HashMap<String, Digest>) -> Self {
+        let pins = pins
+            .into_iter()
+            .map(|(name, digest)| (canonical_name(&name), digest))
+            .collect();
+        self.options.pinned = Arc::new(pins);
+        self
+    }
+
+    /// Apply host policy to every package once its signature and digest are verified,
+    /// before its library is opened. Returning `Err` aborts the load with [`Error::Rejected`].
+    pub fn with_load_filter(
+        mut self,
+        filter: impl Fn(&PackageMetadata) -> std::result::Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.options.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Make a zstd dictionary available to packages exported with it.
+    pub fn with_dictionary(mut self, dict: Dictionary) -> Self {
+        Arc::make_mut(&mut self.options.dictionaries).push(dict);
+        self
+    }
+
+    pub fn strategy(&self) -> LoadStrategy {
+        self.options.strategy
+    }
+
+    /// The loaded plugin `plugin` refers to, see [`PluginRef`].
+    fn find(&self, plugin: PluginRef<'_>) -> Result<&LoadedPlugin> {
+        match plugin {
+            PluginRef::Id(id) => self.plugins.iter().find(|loaded| loaded.id == *id),
+            PluginRef::Name(name) => {
+                let key = canonical_name(name);
+                let mut named = self.plugins.iter().filter(|loaded| loaded.key == key);
+                let found = named.next();
+                if found.is_some() && named.next().is_some() {
+                    return Err(Error::AmbiguousName(name.to_string()));
+                }
+                found
+            }
         }
+        .ok_or_else(|| Error::UnknownPlugin(plugin.to_string()))
+    }
+
+    /// Look up a loaded plugin by id or name, see [`PluginRef`].
+    pub fn get_plugin<'a>(&self, plugin: impl Into<PluginRef<'a>>) -> Option<&dyn Plugin> {
+        self.find(plugin.into())
+            .ok()
+            .map(|loaded| loaded.plugin.as_ref())
+    }
+
+    /// Ids of the loaded plugins named `name`, in load order.
+    pub fn plugin_ids(&self, name: &str) -> Vec<&PluginId> {
+        let key = canonical_name(name);
+        self.plugins
+            .iter()
+            .filter(|loaded| loaded.key == key)
+            .map(|loaded| &loaded.id)
+            .collect()
+    }
+
+    /// Where a loaded plugin came from, looked up like [`PluginManager::get_plugin`].
+    pub fn plugin_source<'a>(&self, plugin: impl Into<PluginRef<'a>>) -> Option<&PluginSource> {
+        self.find(plugin.into()).ok().map(|loaded| &loaded.source)
     }
 
+    /// Resolve an arbitrary symbol exported by the library of a loaded plugin.
+    ///
     /// # Safety
-    /// this api is sound iff when the package is a valid plugin package.
-    pub unsafe fn load_plugin<P: AsRef<Path>>(&mut self, filename: P) -> Result<()> {
-        type PluginCreate =
-            unsafe fn(ROption<RString>, LogCallback) -> RResult<*mut dyn Plugin, PluginError>;
+    /// `T` must match the actual type of the symbol. The returned [`Symbol`] borrows the
+    /// manager, but must also not be used once the plugin has been unloaded.
+    pub unsafe fn get_symbol<'a, T>(
+        &self,
+        plugin: impl Into<PluginRef<'a>>,
+        symbol: &[u8],
+    ) -> Result<Symbol<'_, T>> {
+        let loaded = self.find(plugin.into())?;
+        let missing = || Error::MissingSymbol(String::from_utf8_lossy(symbol).into_owned());
+        // mocks have no library to resolve from
+        let library = loaded
+            .library
+            .and_then(|index| self.loaded_libraries[index].as_ref())
+            .ok_or_else(missing)?;
+        library.get(symbol).map_err(|_| missing())
+    }
 
-        trace!("loading package: {:?}", filename.as_ref());
-        let package = Package::import_file(filename, *VERIFIER_KEY.deref())?;
+    /// Directory the resources of a loaded plugin were released to, next to its library.
+    /// `None` if the plugin is not loaded or its package has no resources.
+    pub fn resource_dir<'a>(&self, plugin: impl Into<PluginRef<'a>>) -> Option<&Path> {
+        self.find(plugin.into())
+            .ok()
+            .and_then(|loaded| loaded.resources.as_ref())
+            .map(|dir| dir.path())
+    }
 
-        trace!("using release-recheck strategy");
-        let (_temp_dir, lib_path) = package.release_lib_to_temp()?;
-        trace!("re-open and lockdown dll from: {:?}", lib_path);
-        let mut lib_file = utils::lock_open_file(&lib_path)?;
-        utils::validate_file(&mut lib_file, package.digest())?;
-        trace!("integrity check passed");
-        let lib = Library::new(&lib_path).map_err(Error::LibraryLoad)?;
+    /// Dependencies declared in the package a loaded plugin came from, looked up like
+    /// [`PluginManager::get_plugin`]. Mocks have none.
+    pub fn dependencies<'a>(&self, plugin: impl Into<PluginRef<'a>>) -> Option<&[DependencySpec]> {
+        self.find(plugin.into())
+            .ok()
+            .map(|loaded| loaded.dependencies())
+    }
 
-        self.loaded_libraries.push(lib);
-        let lib = self.loaded_libraries.last().unwrap();
+    /// Loaded plugins advertising the given interface identifier, by priority and then
+    /// in load order.
+    pub fn plugins_with_interface(&self, id: &str) -> Vec<&dyn Plugin> {
+        self.by_priority()
+            .into_iter()
+            .map(|loaded| loaded.plugin.as_ref())
+            .filter(|plugin| plugin.interfaces().iter().any(|i| i.as_str() == id))
+            .collect()
+    }
 
-        let constructor: Symbol<PluginCreate> = lib
-            .get(b"_comet_plugin_create")
-            .map_err(|_| Error::MissingSymbol("_comet_plugin_create".to_string()))?;
-        let plugin = constructor(RNone, log_callback)
-            .into_result()
-            .map_err(Error::PluginInitialization)?;
-        let plugin = Box::from_raw(plugin);
+    /// How many loaded plugins advertise each interface identifier.
+    pub fn common_interfaces(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for loaded in self.plugins.iter() {
+            for id in loaded.plugin.interfaces() {
+                *counts.entry(id.into_string()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
 
-        let version_req_str = plugin.api_version_require();
-        let version_req =
-            VersionReq::parse(version_req_str.as_str()).map_err(|_| Error::InvalidVersionReq {
-                name: plugin.name().to_string(),
-                req: version_req_str.to_string(),
-            })?;
-        if version_req.matches(&API_VERSION) {
-            debug!("Loaded plugin: {}", plugin.name());
-            plugin.on_plugin_load();
-            self.plugins.push(plugin);
-            Ok(())
-        } else {
-            self.loaded_libraries.pop();
-            Err(Error::InvalidVersionReq {
-                name: plugin.name().to_string(),
-                req: version_req_str.to_string(),
+    /// Pick the interface to speak among `candidates`, given in order of preference.
+    ///
+    /// The candidate supported by the most loaded plugins wins, ties go to the earlier
+    /// candidate. Returns `None` if no plugin supports any of them.
+    pub fn negotiate<'a>(&self, candidates: &[&'a str]) -> Option<&'a str> {
+        let counts = self.common_interfaces();
+        let mut best = None;
+        for &candidate in candidates {
+            let count = counts.get(candidate).copied().unwrap_or(0);
+            if count > best.map_or(0, |(_, n)| n) {
+                best = Some((candidate, count));
+            }
+        }
+        best.map(|(candidate, _)| candidate)
+    }
+
+    /// Deliver an event to every loaded plugin, by priority and then in load order.
+    ///
+    /// A failing plugin doesn't stop the others, the errors are returned with the name
+    /// of the plugin that raised them.
+    pub fn broadcast(&self, event: &str, payload: &[u8]) -> Vec<(String, PluginError)> {
+        let mut errors = Vec::new();
+        for loaded in self.by_priority() {
+            let result = loaded
+                .plugin
+                .on_event(RStr::from_str(event), RSlice::from_slice(payload));
+            if let RErr(e) = result {
+                warn!(
+                    "plugin {} failed to handle event {}: {}",
+                    loaded.key, event, e
+                );
+                errors.push((loaded.plugin.name().to_string(), e));
+            }
+        }
+        errors
+    }
+
+    /// Ask every loaded plugin whether it is healthy, in load order.
+    ///
+    /// Meant to be polled by a supervisor that reloads or unloads the failing ones.
+    pub fn health_check_all(&self) -> Vec<(String, std::result::Result<(), PluginError>)> {
+        self.plugins
+            .iter()
+            .map(|loaded| {
+                let result = loaded.plugin.health_check().into_result();
+                if let Err(e) = &result {
+                    warn!("plugin {} is unhealthy: {}", loaded.key, e);
+                }
+                (loaded.plugin.name().to_string(), result)
             })
+            .collect()
+    }
+
+    /// Loaded plugins, the highest [`PackageMetadata::priority`] first, in load order
+    /// among equal priorities.
+    fn by_priority(&self) -> Vec<&LoadedPlugin> {
+        let mut plugins: Vec<_> = self.plugins.iter().collect();
+        plugins.sort_by_key(|loaded| std::cmp::Reverse(loaded.priority()));
+        plugins
+    }
+
+    /// Hand a new config to a loaded plugin without reloading its library.
+    ///
+    /// Plugins that don't implement [`Plugin::on_reconfigure`] refuse, a full reload
+    /// through [`PluginManager::load_plugin_opts`] with `force` is needed then.
+    pub fn reconfigure<'a>(
+        &self,
+        plugin: impl Into<PluginRef<'a>>,
+        config: String,
+    ) -> Result<(), PluginError> {
+        let loaded = self.find(plugin.into()).map_err(PluginError::custom)?;
+        loaded
+            .plugin
+            .on_reconfigure(RSome(RString::from(config)))
+            .into()
+    }
+
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    pub unsafe fn load_plugin<P: AsRef<Path>>(&mut self, filename: P) -> Result<()> {
+        self.load_plugin_opts(filename, LoadOptions::default())
+            .map(|_| ())
+    }
+
+    /// Load a package unless a plugin with the same digest is already loaded, in which
+    /// case the loaded plugin is described instead. The digest is compared right after
+    /// the package is verified, before its library is opened.
+    ///
+    /// With [`LoadOptions::force`] the package is loaded anyway and replaces the loaded
+    /// copy: the new plugin is constructed while the old one still runs, and the old one
+    /// is only unloaded once the new one is admitted, within its
+    /// [`Plugin::preferred_shutdown`].
+    ///
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    pub unsafe fn load_plugin_opts<P: AsRef<Path>>(
+        &mut self,
+        filename: P,
+        opts: LoadOptions,
+    ) -> Result<PluginInfo> {
+        let path = filename.as_ref().to_path_buf();
+        let content = loader::read_package(&path)?;
+        let mut timings = LoadTimings::default();
+        #[cfg(feature = "dev-unsafe")]
+        let (package, source) = if self.dev_mode && dev::is_raw_library(&content) {
+            let package = dev::package_raw_library(&path, content)?;
+            (package, PluginSource::Unsigned(path))
+        } else {
+            let package = loader::import_package(&content, &self.options, &mut timings)?;
+            (package, PluginSource::File(path))
+        };
+        #[cfg(not(feature = "dev-unsafe"))]
+        let (package, source) = (
+            loader::import_package(&content, &self.options, &mut timings)?,
+            PluginSource::File(path),
+        );
+        let index = self.install_package(package, source, opts, &mut timings)?;
+        Ok(self.package_info(&self.plugins[index]).unwrap())
+    }
+
+    /// Open a verified package and admit its plugin, the path every load takes once the
+    /// package is imported. Returns the index of the plugin in `plugins`.
+    ///
+    /// A package with the digest of a loaded plugin is not opened again, the loaded plugin
+    /// is kept, unless [`LoadOptions::force`] replaces it, see
+    /// [`PluginManager::load_plugin_opts`].
+    unsafe fn install_package(
+        &mut self,
+        package: Package,
+        source: PluginSource,
+        opts: LoadOptions,
+        timings: &mut LoadTimings,
+    ) -> Result<usize> {
+        let admission = self.admission(&package, opts.force)?;
+        if let Admission::Loaded(index) = admission {
+            return Ok(index);
         }
+        let result = loader::open_package(package, &self.options, timings).and_then(|opened| {
+            let start = Instant::now();
+            let result = self.register_library(opened, source, opts.config);
+            timings.construct = start.elapsed();
+            result
+        });
+        self.settle(result, admission)
     }
-}
 
-#[macro_export]
-macro_rules! declare_plugin {
-    ($plugin_type:ty, $constructor:path) => {
-        #[no_mangle]
-        pub extern "C" fn _comet_plugin_create(
-            config: $crate::abi_stable::std_types::ROption<$crate::abi_stable::std_types::RString>,
-            log_callback: LogCallback,
-        ) -> $crate::abi_stable::std_types::RResult<*mut dyn $crate::Plugin, $crate::PluginError> {
-            // make sure the constructor is the correct type.
-            let constructor: fn(
-                Option<String>,
-                log_callback: LogCallback,
-            ) -> Result<$plugin_type, $crate::PluginError> = $constructor;
+    /// Compare a verified package with the loaded plugins by digest, before its library is
+    /// opened. A plugin about to be replaced is taken out so the new copy doesn't clash
+    /// with its id.
+    fn admission(&mut self, package: &Package, force: bool) -> Result<Admission> {
+        let existing = self.plugins.iter().position(|loaded| {
+            loaded
+                .metadata
+                .as_ref()
+                .is_some_and(|m| m.digest.eq_ignore_ascii_case(&package.metadata.digest))
+        });
+        match existing {
+            Some(index) if !force => {
+                debug!("{} is already loaded", package.metadata.name);
+                Ok(Admission::Loaded(index))
+            }
+            Some(index) => Ok(Admission::Replace(
+                index,
+                Box::new(self.plugins.remove(index)),
+            )),
+            None => {
+                self.check_capacity(1)?;
+                Ok(Admission::New)
+            }
+        }
+    }
 
-            let object = constructor(config.into_option().map(|s| s.into_string()), log_callback);
-            match object {
-                Ok(plugin) => {
-                    let boxed: Box<dyn $crate::Plugin> = Box::new(plugin);
-                    $crate::abi_stable::std_types::ROk(Box::into_raw(boxed))
+    /// Finish a load started with [`PluginManager::admission`]: on failure a replaced
+    /// plugin is put back, on success it is unloaded and its library closed. Returns the
+    /// index of the plugin now loaded.
+    fn settle(&mut self, result: Result<()>, admission: Admission) -> Result<usize> {
+        match (result, admission) {
+            (Err(e), Admission::Replace(index, old)) => {
+                self.plugins.insert(index, *old);
+                Err(e)
+            }
+            (Err(e), _) => Err(e),
+            (Ok(()), Admission::Replace(_, old)) => {
+                let name = old.plugin.name().to_string();
+                // bounded by its shutdown window like any other unload
+                if let Err(e) = self.close_plugin(*old) {
+                    warn!("replaced plugin {} failed to unload: {}", name, e);
                 }
-                Err(e) => $crate::abi_stable::std_types::RErr(e),
+                debug!("Replaced plugin: {}", name);
+                Ok(self.plugins.len() - 1)
             }
+            (Ok(()), Admission::Loaded(index)) => Ok(index),
+            (Ok(()), Admission::New) => Ok(self.plugins.len() - 1),
         }
-    };
-}
+    }
 
-#[test]
-fn test_load() {
-    pretty_env_logger::init();
-    let mut plugin_mgr = PluginManager::new();
-    unsafe {
-        plugin_mgr
-            .load_plugin(
-                r"/Users/lightsing/workspace/GitHub/comet-dynamic-platform/target/debug/spider.cdp",
-            )
-            .unwrap();
+    /// Describe a plugin loaded from a package, `None` for mocks.
+    fn package_info(&self, loaded: &LoadedPlugin) -> Option<PluginInfo> {
+        let metadata = loaded.metadata.clone()?;
+        let has_config_schema = loaded
+            .library
+            .and_then(|index| self.loaded_libraries[index].as_ref())
+            .is_some_and(|library| unsafe { read_config_schema(library).is_some() });
+        Some(PluginInfo {
+            metadata,
+            has_config_schema,
+            source: loaded.source.clone(),
+        })
+    }
+
+    /// Like [`PluginManager::load_plugin`], but reports how long each step took.
+    ///
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    pub unsafe fn load_plugin_with_report<P: AsRef<Path>>(
+        &mut self,
+        filename: P,
+    ) -> Result<LoadReport> {
+        let source = PluginSource::File(filename.as_ref().to_path_buf());
+        let mut timings = LoadTimings::default();
+        let content = loader::read_package(filename)?;
+        let package = loader::import_package(&content, &self.options, &mut timings)?;
+        let (name, version, digest) = (
+            package.metadata.name.clone(),
+            package.metadata.version.clone(),
+            package.metadata.digest.clone(),
+        );
+        self.install_package(
+            package,
+            source.clone(),
+            LoadOptions::default(),
+            &mut timings,
+        )?;
+        Ok(LoadReport {
+            name,
+            version,
+            digest,
+            source,
+            timings,
+        })
+    }
+
+    /// Like [`PluginManager::load_plugin`], but from a package already read into memory.
+    ///
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    pub unsafe fn load_plugin_bytes(&mut self, exported: &[u8]) -> Result<()> {
+        self.install_bytes(exported, PluginSource::Bytes)
+    }
+
+    /// Import a package from memory and install it, see [`PluginManager::install_package`].
+    unsafe fn install_bytes(&mut self, exported: &[u8], source: PluginSource) -> Result<()> {
+        let mut timings = LoadTimings::default();
+        let package = loader::import_package(exported, &self.options, &mut timings)?;
+        self.install_package(package, source, LoadOptions::default(), &mut timings)
+            .map(|_| ())
+    }
+
+    /// Load a package compiled into the host binary, for single-binary deployments:
+    ///
+    /// ```ignore
+    /// unsafe { plugin_mgr.load_embedded(include_bytes!("../plugins/spider.cdp")) }?;
+    /// ```
+    ///
+    /// The package is verified like any other. `dlopen` needs a file, so the library is
+    /// still released to a writable temp dir, the embedded bytes are only read.
+    ///
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    pub unsafe fn load_embedded(&mut self, package: &'static [u8]) -> Result<()> {
+        self.install_bytes(package, PluginSource::Embedded)
     }
+
+    /// Fetch the package of plugin `name` in a version matching `req` from the
+    /// [`PackageSource`] and load it from memory, see [`PluginManager::load_plugin_bytes`].
+    ///
+    /// The fetched package is verified like any other, and must be the plugin and version
+    /// asked for.
+    ///
+    /// # Safety
+    /// this api is sound iff when the fetched package is a valid plugin package.
+    pub unsafe fn load_plugin_by_name(&mut self, name: &str, req: &VersionReq) -> Result<()> {
+        let source = self.package_source.clone().ok_or(Error::NoPackageSource)?;
+        let exported = source.fetch(name, req)?;
+        let mut timings = LoadTimings::default();
+        let package = loader::import_package(&exported, &self.options, &mut timings)?;
+        if canonical_name(&package.metadata.name) != canonical_name(name)
+            || !req.matches(&package.metadata.version)
+        {
+            return Err(Error::PackageNotFound {
+                name: name.to_string(),
+                req: req.to_string(),
+            });
+        }
+        self.install_package(
+            package,
+            PluginSource::Bytes,
+            LoadOptions::default(),
+            &mut timings,
+        )
+        .map(|_| ())
+    }
+
+    /// Load every package in a directory, in file name order.
+    ///
+    /// Returns the outcome for each file considered. When any extension is accepted,
+    /// files that cannot be parsed as a package are skipped instead of reported,
+    /// packages failing verification are still reported.
+    /// The released library always gets the platform extension, whatever the package is named.
+    ///
+    /// # Safety
+    /// this api is sound iff when all packages in the directory are valid plugin packages.
+    pub unsafe fn load_plugin_dir<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+    ) -> Result<Vec<(PathBuf, Result<()>)>> {
+        let paths = self.package_files(dir.as_ref())?;
+        let mut results = Vec::with_capacity(paths.len());
+        for path in paths {
+            let result = self.load_plugin(&path);
+            if self.package_extension.is_none() {
+                if let Err(Error::InvalidPackage(
+                    e @ (plugin_defs::Error::Bincode(_) | plugin_defs::Error::BadMagic),
+                )) = &result
+                {
+                    debug!("skip {:?}, not a package: {}", path, e);
+                    continue;
+                }
+            }
+            results.push((path, result));
+        }
+        Ok(results)
+    }
+
+    /// Files of a directory with the package extension, in file name order.
+    fn package_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(extension) = &self.package_extension {
+                if path.extension() != Some(OsStr::new(extension)) {
+                    continue;
+                }
+            }
+            paths.push(path);
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Load exactly the builds listed in a [`PluginLock`], from the packages in `search_dir`.
+    ///
+    /// The lock should have been checked with [`PluginLock::verify`]. Every locked plugin
+    /// needs a package in the directory with the locked version and digest, other packages
+    /// are ignored. A locked build that fails verification, e.g. because it was revoked or
+    /// signed with another key, is reported with that error rather than
+    /// [`Error::LockMismatch`]. All packages are verified and matched before any is loaded,
+    /// then loaded so that each comes after the locked plugins it depends on. Loading stops
+    /// at the first failure, the plugins loaded before it stay loaded.
+    ///
+    /// # Safety
+    /// this api is sound iff when all packages in the directory are valid plugin packages.
+    pub unsafe fn load_from_lock<P: AsRef<Path>>(
+        &mut self,
+        lock: &PluginLock,
+        search_dir: P,
+    ) -> Result<()> {
+        // keyed by id, or by name for packages without one, see `PluginLock::key`
+        let locked: HashMap<String, &LockedPlugin> = lock
+            .plugins
+            .iter()
+            .map(|(key, locked)| (canonical_name(key), locked))
+            .collect();
+        let is_locked = |metadata: &PackageMetadata| {
+            let key = canonical_name(PluginLock::key(metadata));
+            let matches = locked.get(&key).is_some_and(|locked| {
+                locked.version == metadata.version
+                    && locked.digest.eq_ignore_ascii_case(&metadata.digest)
+            });
+            matches.then_some(key)
+        };
+        let mut found = BTreeMap::new();
+        // why the packages claiming a locked build could not be used
+        let mut failures = HashMap::new();
+        for path in self.package_files(search_dir.as_ref())? {
+            let exported = loader::read_package(&path)?;
+            let package =
+                match loader::import_package(&exported, &self.options, &mut LoadTimings::default())
+                {
+                    Ok(package) => package,
+                    Err(e) => {
+                        debug!("skip {:?} for the lock: {}", path, e);
+                        // the unverified metadata only tells which error to report
+                        if let Some(key) = Package::peek_metadata(&exported)
+                            .ok()
+                            .and_then(|metadata| is_locked(&metadata))
+                        {
+                            failures.entry(key).or_insert(e);
+                        }
+                        continue;
+                    }
+                };
+            if let Some(key) = is_locked(&package.metadata) {
+                found.entry(key).or_insert((path, package));
+            }
+        }
+        for key in lock.plugins.keys() {
+            let key_canonical = canonical_name(key);
+            if !found.contains_key(&key_canonical) {
+                return Err(failures
+                    .remove(&key_canonical)
+                    .unwrap_or_else(|| Error::LockMismatch(key.clone())));
+            }
+        }
+        self.check_capacity(found.len())?;
+
+        for (path, package) in loader::dependency_order(found.into_values().collect())? {
+            self.install_package(
+                package,
+                PluginSource::File(path),
+                LoadOptions::default(),
+                &mut LoadTimings::default(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Load every package of a tar or zip archive, see [`PluginManager::load_plugin_bytes`].
+    ///
+    /// Entries are filtered by extension like in [`PluginManager::load_plugin_dir`]. If the
+    /// archive has a `manifest.json` mapping each entry to the hex digest of its library,
+    /// the bundle must match it exactly. All packages are verified before any is loaded,
+    /// then loaded so that each comes after the bundled packages it depends on.
+    ///
+    /// Returns the outcome for each package, keyed by its path in the archive.
+    ///
+    /// # Safety
+    /// this api is sound iff when all packages in the archive are valid plugin packages.
+    #[cfg(feature = "bundle")]
+    pub unsafe fn load_bundle<P: AsRef<Path>>(
+        &mut self,
+        archive: P,
+    ) -> Result<Vec<(String, Result<()>)>> {
+        let mut entries = bundle::read_archive(archive.as_ref())?;
+        let manifest = entries
+            .remove(bundle::MANIFEST_NAME)
+            .map(|manifest| bundle::BundleManifest::parse(&manifest))
+            .transpose()?;
+
+        let mut packages = Vec::new();
+        for (entry, exported) in entries {
+            if let Some(extension) = &self.package_extension {
+                if Path::new(&entry).extension() != Some(OsStr::new(extension)) {
+                    continue;
+                }
+            }
+            match loader::import_package(&exported, &self.options, &mut LoadTimings::default()) {
+                Ok(package) => packages.push((entry, package)),
+                Err(Error::InvalidPackage(
+                    e @ (plugin_defs::Error::Bincode(_) | plugin_defs::Error::BadMagic),
+                )) if self.package_extension.is_none() => {
+                    debug!("skip bundle entry {}, not a package: {}", entry, e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(manifest) = manifest {
+            manifest.check(&packages)?;
+        }
+
+        let mut results = Vec::with_capacity(packages.len());
+        for (entry, package) in loader::dependency_order(packages)? {
+            let result = self
+                .install_package(
+                    package,
+                    PluginSource::Bytes,
+                    LoadOptions::default(),
+                    &mut LoadTimings::default(),
+                )
+                .map(|_| ());
+            results.push((entry, result));
+        }
+        Ok(results)
+    }
+
+    /// Like [`PluginManager::load_plugin`], but runs the blocking steps (reading, hashing,
+    /// releasing and opening the library) on tokio's blocking pool.
+    ///
+    /// Only the opened [`Library`] crosses back to the caller, the plugin itself is
+    /// constructed on the calling task.
+    ///
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    #[cfg(feature = "tokio")]
+    pub async unsafe fn load_plugin_async<P: AsRef<Path>>(&mut self, filename: P) -> Result<()> {
+        let filename = filename.as_ref().to_path_buf();
+        let source = PluginSource::File(filename.clone());
+        let options = self.options.clone();
+        let package = tokio::task::spawn_blocking(move || {
+            let content = loader::read_package(filename)?;
+            loader::import_package(&content, &options, &mut LoadTimings::default())
+        })
+        .await
+        .map_err(Error::Join)??;
+        // compared by digest like every other load, before the library is opened
+        let admission = self.admission(&package, false)?;
+        if let Admission::Loaded(_) = admission {
+            return Ok(());
+        }
+        let options = self.options.clone();
+        let result = tokio::task::spawn_blocking(move || unsafe {
+            loader::open_package(package, &options, &mut LoadTimings::default())
+        })
+        .await
+        .map_err(Error::Join)
+        .and_then(|opened| opened)
+        .and_then(|opened| self.register_library(opened, source, None));
+        self.settle(result, admission).map(|_| ())
+    }
+
+    /// Construct the plugin from an opened library and keep both if the API version matches.
+    unsafe fn register_library(
+        &mut self,
+        opened: OpenedLibrary,
+        source: PluginSource,
+        config: Option<String>,
+    ) -> Result<()> {
+        let OpenedLibrary {
+            metadata,
+            library: lib,
+            resources,
+        } = opened;
+        // the constructor already returns a `PluginError`, so a plugin built against an
+        // incompatible plugin-base is refused before it is called
+        if let Some(req) = built_against_req(&metadata)? {
+            if !req.matches(&API_VERSION) {
+                return Err(self.unmet_requirement(
+                    &metadata.name,
+                    &req.to_string(),
+                    Some(API_VERSION),
+                ));
+            }
+        }
+        let plugin = {
+            let constructor: PluginCreate = *lib
+                .get::<PluginCreate>(b"_comet_plugin_create")
+                .map_err(|_| Error::MissingSymbol("_comet_plugin_create".to_string()))?;
+            let config = config.map(RString::from).into();
+            let created = match self.options.load_timeout {
+                None => constructor(config, log_callback),
+                Some(timeout) => match construct_with_timeout(constructor, config, timeout)? {
+                    Some(created) => created,
+                    None => {
+                        warn!(
+                            "plugin {} was not constructed within {:?}, leaking its library",
+                            metadata.name, timeout
+                        );
+                        std::mem::forget(lib);
+                        std::mem::forget(resources);
+                        return Err(Error::LoadTimeout {
+                            name: metadata.name,
+                            timeout,
+                        });
+                    }
+                },
+            };
+            Box::from_raw(created.into_result().map_err(Error::PluginInitialization)?)
+        };
+        // on error the plugin is dropped before its library
+        let library = self.loaded_libraries.len();
+        self.admit_plugin(plugin, Some(metadata), source, Some(library), resources)?;
+        self.loaded_libraries.push(Some(lib));
+        Ok(())
+    }
+
+    /// Install an in-process plugin without any package, library or signature.
+    /// It still has to pass the API version check.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn load_mock(&mut self, plugin: Box<dyn Plugin>) -> Result<()> {
+        self.check_capacity(1)?;
+        self.admit_plugin(plugin, None, PluginSource::Mock, None, None)
+    }
+
+    /// Fail with [`Error::CapacityExceeded`] if `additional` more plugins would exceed
+    /// [`PluginManager::with_max_plugins`].
+    fn check_capacity(&self, additional: usize) -> Result<()> {
+        let loaded = self.plugins.len();
+        match self.max_plugins {
+            Some(limit) if loaded + additional > limit => {
+                Err(Error::CapacityExceeded { limit, loaded })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Report an unmet requirement to the callback and build the error for it.
+    fn unmet_requirement(&self, name: &str, req: &str, actual: Option<Version>) -> Error {
+        warn!("skip plugin {}: requires {}", name, req);
+        if let Some(callback) = self.unmet_requirement {
+            callback(name, req, actual.as_ref());
+        }
+        Error::UnmetRequirement {
+            name: name.to_string(),
+            req: req.to_string(),
+            actual,
+        }
+    }
+
+    /// Check the version requirement of a constructed plugin and keep it if it matches.
+    fn admit_plugin(
+        &mut self,
+        plugin: Box<dyn Plugin>,
+        metadata: Option<PackageMetadata>,
+        source: PluginSource,
+        library: Option<usize>,
+        resources: Option<TempDir>,
+    ) -> Result<()> {
+        let version_req_str = plugin.api_version_require();
+        let version_req =
+            VersionReq::parse(version_req_str.as_str()).map_err(|_| Error::InvalidVersionReq {
+                name: plugin.name().to_string(),
+                req: version_req_str.to_string(),
+            })?;
+        if let Some(built_against) = metadata
+            .as_ref()
+            .and_then(|m| m.plugin_base_version.as_ref())
+        {
+            if !version_req.matches(built_against) {
+                warn!(
+                    "plugin {} requires API {} but was built against plugin-base {}, the requirement may be stale",
+                    plugin.name(),
+                    version_req,
+                    built_against
+                );
+            }
+        }
+        if !version_req.matches(&API_VERSION) {
+            return Err(self.unmet_requirement(
+                plugin.name().as_str(),
+                version_req_str.as_str(),
+                Some(API_VERSION),
+            ));
+        }
+        if let RSome(host_req_str) = plugin.host_version_require() {
+            let host_req =
+                VersionReq::parse(host_req_str.as_str()).map_err(|_| Error::InvalidVersionReq {
+                    name: plugin.name().to_string(),
+                    req: host_req_str.to_string(),
+                })?;
+            // a host that doesn't declare its version cannot satisfy any requirement
+            if !self
+                .host_version
+                .as_ref()
+                .is_some_and(|version| host_req.matches(version))
+            {
+                return Err(self.unmet_requirement(
+                    plugin.name().as_str(),
+                    host_req_str.as_str(),
+                    self.host_version.clone(),
+                ));
+            }
+        }
+        let key = canonical_name(plugin.name().as_str());
+        if key.is_empty() {
+            return Err(Error::InvalidPluginName(plugin.name().to_string()));
+        }
+        // the signed metadata name must be the name of the code that was loaded
+        if let Some(metadata) = &metadata {
+            if canonical_name(&metadata.name) != key {
+                return Err(Error::NameMismatch {
+                    metadata: metadata.name.clone(),
+                    runtime: plugin.name().to_string(),
+                });
+            }
+        }
+        let id = PluginId::of(metadata.as_ref(), &key);
+        if self.plugins.iter().any(|loaded| loaded.id == id) {
+            return Err(Error::DuplicatePlugin(id.to_string()));
+        }
+        debug!("Loaded plugin: {}", plugin.name());
+        plugin.on_plugin_load();
+        self.plugins.push(LoadedPlugin {
+            key,
+            id,
+            plugin,
+            source,
+            metadata,
+            library,
+            resources,
+        });
+        Ok(())
+    }
+
+    /// Replace every loaded plugin with the packages at `paths`, all or nothing.
+    ///
+    /// The new plugins are loaded into a staging manager with the same configuration
+    /// first, so they are constructed while the old ones still run. Only if every package
+    /// loads are the old plugins unloaded and the new ones installed, otherwise the staged
+    /// plugins are unloaded again and the old set is left untouched.
+    ///
+    /// # Safety
+    /// this api is sound iff when all packages are valid plugin packages.
+    pub unsafe fn reload_all(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let mut staging = PluginManager {
+            plugins: Vec::new(),
+            loaded_libraries: Vec::new(),
+            options: self.options.clone(),
+            package_extension: self.package_extension.clone(),
+            host_version: self.host_version.clone(),
+            unmet_requirement: self.unmet_requirement,
+            max_plugins: self.max_plugins,
+            package_source: self.package_source.clone(),
+            #[cfg(feature = "dev-unsafe")]
+            dev_mode: self.dev_mode,
+        };
+        for path in paths {
+            staging.load_plugin(path)?;
+        }
+        self.unload_all();
+        self.plugins = std::mem::take(&mut staging.plugins);
+        self.loaded_libraries = std::mem::take(&mut staging.loaded_libraries);
+        Ok(())
+    }
+
+    /// Unload every plugin, each before the plugins it depends on, then close the libraries.
+    ///
+    /// Plugins without dependencies between them are unloaded in reverse load order.
+    /// Failing unload hooks are only logged, use [`PluginManager::shutdown`] to get them.
+    pub fn unload_all(&mut self) {
+        for (name, result) in self.unload_plugins() {
+            if let Err(e) = result {
+                warn!("plugin {} failed to unload: {}", name, e);
+            }
+        }
+        self.loaded_libraries.clear();
+    }
+
+    /// Unload every plugin like [`PluginManager::unload_all`], returning the result of
+    /// each unload hook in the order they ran, and close the libraries.
+    pub fn shutdown(mut self) -> Vec<(String, std::result::Result<(), PluginError>)> {
+        let results = self.unload_plugins();
+        self.loaded_libraries.clear();
+        results
+    }
+
+    /// Unload the plugins in dependency order, keeping their libraries open.
+    ///
+    /// Each unload hook runs for at most [`Plugin::preferred_shutdown`], a plugin still
+    /// unloading after that is reported as [`PluginError::Timeout`] and leaked with its
+    /// library, which is never closed.
+    fn unload_plugins(&mut self) -> Vec<(String, std::result::Result<(), PluginError>)> {
+        let mut results = Vec::with_capacity(self.plugins.len());
+        while !self.plugins.is_empty() {
+            // a plugin no remaining plugin depends on, latest loaded first
+            let leaf = self
+                .plugins
+                .iter()
+                .rposition(|candidate| {
+                    !self.plugins.iter().any(|other| other.depends_on(candidate))
+                })
+                .unwrap_or(self.plugins.len() - 1);
+            let loaded = self.plugins.remove(leaf);
+            let name = loaded.plugin.name().to_string();
+            let result = self.run_unload(loaded);
+            results.push((name, result));
+        }
+        results
+    }
+
+    /// Run the unload hook of a plugin taken out of `plugins` for at most
+    /// [`Plugin::preferred_shutdown`], keeping its library open.
+    ///
+    /// A plugin still unloading after that is reported as [`PluginError::Timeout`] and
+    /// leaked with its library and resources, which are never closed.
+    fn run_unload(&mut self, loaded: LoadedPlugin) -> std::result::Result<(), PluginError> {
+        let LoadedPlugin {
+            plugin,
+            library,
+            resources,
+            ..
+        } = loaded;
+        let name = plugin.name().to_string();
+        let timeout = plugin.preferred_shutdown().into();
+        match unload_with_timeout(plugin, timeout) {
+            Some(result) => {
+                debug!("Unloaded plugin: {}", name);
+                result
+            }
+            None => {
+                warn!(
+                    "plugin {} did not unload within {:?}, leaking it",
+                    name, timeout
+                );
+                if let Some(library) = library.and_then(|index| self.loaded_libraries[index].take())
+                {
+                    std::mem::forget(library);
+                }
+                std::mem::forget(resources);
+                Err(PluginError::Timeout)
+            }
+        }
+    }
+
+    /// Unload a single plugin and close its library, see [`PluginManager::unload_all`].
+    ///
+    /// Refused while another loaded plugin depends on it, those have to be unloaded first.
+    /// The unload hook runs for at most [`Plugin::preferred_shutdown`] like on shutdown.
+    pub fn unload_plugin<'a>(
+        &mut self,
+        plugin: impl Into<PluginRef<'a>>,
+    ) -> Result<(), PluginError> {
+        let loaded = self.find(plugin.into()).map_err(PluginError::custom)?;
+        if let Some(dependent) = self.plugins.iter().find(|other| other.depends_on(loaded)) {
+            return Err(PluginError::custom(format!(
+                "plugin {} depends on {}",
+                dependent.id, loaded.id
+            )));
+        }
+        let index = self
+            .plugins
+            .iter()
+            .position(|other| other.id == loaded.id)
+            .unwrap();
+        let loaded = self.plugins.remove(index);
+        self.close_plugin(loaded)
+    }
+
+    /// Unload a plugin taken out of `plugins` like [`PluginManager::run_unload`], then close
+    /// its library.
+    fn close_plugin(&mut self, loaded: LoadedPlugin) -> std::result::Result<(), PluginError> {
+        let library = loaded.library;
+        let result = self.run_unload(loaded);
+        // the plugin is gone, a leaked library was already taken out
+        if let Some(index) = library {
+            self.loaded_libraries[index] = None;
+        }
+        result
+    }
+
+    /// Dry-run a load: verify the package, release and open the library and resolve the
+    /// constructor symbol, but never construct the plugin.
+    ///
+    /// The runtime version requirement is only reported by a constructed plugin, so the
+    /// plugin-base version recorded at pack time is checked against [`API_VERSION`] instead.
+    ///
+    /// # Safety
+    /// opening the library runs its initializers, this api is sound iff when the package is
+    /// a valid plugin package.
+    pub unsafe fn validate_plugin<P: AsRef<Path>>(&self, filename: P) -> Result<PluginInfo> {
+        let source = PluginSource::File(filename.as_ref().to_path_buf());
+        let OpenedLibrary {
+            metadata,
+            library: lib,
+            ..
+        } = loader::open_library(filename, &self.options, &mut LoadTimings::default())?;
+        lib.get::<*const ()>(b"_comet_plugin_create")
+            .map_err(|_| Error::MissingSymbol("_comet_plugin_create".to_string()))?;
+        let has_config_schema = read_config_schema(&lib).is_some();
+        if let Some(req) = built_against_req(&metadata)? {
+            if !req.matches(&API_VERSION) {
+                return Err(Error::UnmetRequirement {
+                    name: metadata.name,
+                    req: req.to_string(),
+                    actual: Some(API_VERSION),
+                });
+            }
+        }
+        Ok(PluginInfo {
+            metadata,
+            has_config_schema,
+            source,
+        })
+    }
+
+    /// Read the config JSON schema a package declares via `declare_plugin!`,
+    /// without constructing the plugin.
+    ///
+    /// # Safety
+    /// this api is sound iff when the package is a valid plugin package.
+    pub unsafe fn config_schema<P: AsRef<Path>>(&self, filename: P) -> Result<Option<String>> {
+        let OpenedLibrary { library: lib, .. } =
+            loader::open_library(filename, &self.options, &mut LoadTimings::default())?;
+        Ok(read_config_schema(&lib))
+    }
+}
+
+/// The requirement on [`API_VERSION`] of a package built against the recorded plugin-base
+/// version, `None` if the packer did not record one.
+fn built_against_req(metadata: &PackageMetadata) -> Result<Option<VersionReq>> {
+    let Some(built_against) = &metadata.plugin_base_version else {
+        return Ok(None);
+    };
+    let req = format!("^{built_against}");
+    VersionReq::parse(&req)
+        .map(Some)
+        .map_err(|_| Error::InvalidVersionReq {
+            name: metadata.name.clone(),
+            req,
+        })
+}
+
+/// Call the schema accessor `declare_plugin!` exports, `None` if the library has none or
+/// declares no schema.
+///
+/// # Safety
+/// the library must export the accessor with the signature `declare_plugin!` gives it.
+unsafe fn read_config_schema(lib: &Library) -> Option<String> {
+    type ConfigSchema = unsafe extern "C" fn() -> ROption<RStr<'static>>;
+
+    let schema = lib
+        .get::<ConfigSchema>(b"_comet_plugin_config_schema")
+        .ok()?;
+    // copy out before the library is unloaded
+    schema().into_option().map(|s| s.to_string())
+}
+
+/// Export the plugin constructor, and optionally a JSON schema for its config.
+///
+/// ```ignore
+/// declare_plugin!(Spider, create_plugin);
+/// declare_plugin!(Spider, create_plugin, SCHEMA);
+/// ```
+///
+/// The constructor reports failures the host can tell apart with the structured
+/// [`PluginError`] variants, and anything else with [`PluginError::custom`]:
+///
+/// ```ignore
+/// fn create_plugin(config: Option<String>, log_callback: LogCallback) -> Result<Spider, PluginError> {
+///     let config: Config = plugin_base::config::parse_json(config)?;
+///     let seeds = std::fs::read_to_string(&config.seeds)
+///         .map_err(|_| PluginError::ResourceNotFound(config.seeds.into()))?;
+///     Ok(Spider::new(seeds))
+/// }
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    (@create $plugin_type:ty, $constructor:path) => {
+        #[no_mangle]
+        pub extern "C" fn _comet_plugin_create(
+            config: $crate::abi_stable::std_types::ROption<$crate::abi_stable::std_types::RString>,
+            log_callback: LogCallback,
+        ) -> $crate::abi_stable::std_types::RResult<*mut dyn $crate::Plugin, $crate::PluginError> {
+            // make sure the constructor is the correct type.
+            let constructor: fn(
+                Option<String>,
+                log_callback: LogCallback,
+            ) -> Result<$plugin_type, $crate::PluginError> = $constructor;
+
+            let object = $crate::panic::catch_constructor_panic(|| {
+                constructor(config.into_option().map(|s| s.into_string()), log_callback)
+            });
+            match object {
+                Ok(plugin) => {
+                    let boxed: Box<dyn $crate::Plugin> = Box::new(plugin);
+                    $crate::abi_stable::std_types::ROk(Box::into_raw(boxed))
+                }
+                Err(e) => $crate::abi_stable::std_types::RErr(e),
+            }
+        }
+    };
+    ($plugin_type:ty, $constructor:path) => {
+        $crate::declare_plugin!(@create $plugin_type, $constructor);
+
+        #[no_mangle]
+        pub extern "C" fn _comet_plugin_config_schema() -> $crate::abi_stable::std_types::ROption<
+            $crate::abi_stable::std_types::RStr<'static>,
+        > {
+            $crate::abi_stable::std_types::RNone
+        }
+    };
+    ($plugin_type:ty, $constructor:path, $schema:expr) => {
+        $crate::declare_plugin!(@create $plugin_type, $constructor);
+
+        #[no_mangle]
+        pub extern "C" fn _comet_plugin_config_schema() -> $crate::abi_stable::std_types::ROption<
+            $crate::abi_stable::std_types::RStr<'static>,
+        > {
+            let schema: &'static str = $schema;
+            $crate::abi_stable::std_types::RSome($crate::abi_stable::std_types::RStr::from_str(schema))
+        }
+    };
+}
+
+#[test]
+fn test_load_verifying_key_pem_rejects_malformed() {
+    assert!(matches!(
+        load_verifying_key_pem(b"not a pem"),
+        Err(Error::InvalidVerifierKey(_))
+    ));
+    let short = pem::encode(&pem::Pem {
+        tag: "ED25519 DALEK PUBLIC KEY".to_string(),
+        contents: vec![0; 16],
+    });
+    assert!(matches!(
+        load_verifying_key_pem(short.as_bytes()),
+        Err(Error::InvalidVerifierKey(_))
+    ));
+}
+
+#[cfg(test)]
+struct MockPlugin {
+    name: &'static str,
+    req: &'static str,
+}
+
+#[cfg(test)]
+impl Plugin for MockPlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str(self.name)
+    }
+
+    fn api_version_require(&self) -> RStr<'static> {
+        RStr::from_str(self.req)
+    }
+
+    fn interfaces(&self) -> RVec<RString> {
+        RVec::from(vec![RString::from("comet.mock.v1")])
+    }
+}
+
+#[test]
+fn test_load_mock_checks_version() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "mock",
+            req: "^0.2",
+        }))
+        .unwrap();
+    assert_eq!(plugin_mgr.plugins_with_interface("comet.mock.v1").len(), 1);
+    assert_eq!(plugin_mgr.plugin_source("mock"), Some(&PluginSource::Mock));
+    assert!(matches!(
+        unsafe { plugin_mgr.get_symbol::<fn()>("mock", b"_comet_plugin_create") },
+        Err(Error::MissingSymbol(_))
+    ));
+    assert!(matches!(
+        unsafe { plugin_mgr.get_symbol::<fn()>("other", b"_comet_plugin_create") },
+        Err(Error::UnknownPlugin(_))
+    ));
+    assert!(matches!(
+        plugin_mgr.load_mock(Box::new(MockPlugin {
+            name: "mock-new",
+            req: ">=99",
+        })),
+        Err(Error::UnmetRequirement { .. })
+    ));
+    assert!(matches!(
+        plugin_mgr.load_mock(Box::new(MockPlugin {
+            name: "mock-bad",
+            req: "nonsense",
+        })),
+        Err(Error::InvalidVersionReq { .. })
+    ));
+}
+
+#[test]
+fn test_duplicate_names_are_normalized() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "Spider",
+            req: "^0.2",
+        }))
+        .unwrap();
+    for name in [" spider ", "SPIDER", "spider\n"] {
+        assert!(matches!(
+            plugin_mgr.load_mock(Box::new(MockPlugin { name, req: "^0.2" })),
+            Err(Error::DuplicatePlugin(_))
+        ));
+    }
+    assert!(plugin_mgr.get_plugin("  sPiDeR").is_some());
+    assert!(matches!(
+        plugin_mgr.load_mock(Box::new(MockPlugin {
+            name: "   ",
+            req: "^0.2"
+        })),
+        Err(Error::InvalidPluginName(_))
+    ));
+}
+
+#[cfg(test)]
+struct HostBoundPlugin;
+
+#[cfg(test)]
+impl Plugin for HostBoundPlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str("host-bound")
+    }
+
+    fn host_version_require(&self) -> ROption<RStr<'static>> {
+        RSome(RStr::from_str("^1.4"))
+    }
+}
+
+#[test]
+fn test_host_version_require() {
+    static SKIPPED: std::sync::Mutex<Vec<(String, Option<Version>)>> =
+        std::sync::Mutex::new(Vec::new());
+    fn record(name: &str, _: &str, actual: Option<&Version>) {
+        SKIPPED
+            .lock()
+            .unwrap()
+            .push((name.to_string(), actual.cloned()));
+    }
+
+    let mut plugin_mgr = PluginManager::new();
+    assert!(matches!(
+        plugin_mgr.load_mock(Box::new(HostBoundPlugin)),
+        Err(Error::UnmetRequirement { actual: None, .. })
+    ));
+    let mut plugin_mgr = PluginManager::new_with_host_version(Version::new(1, 3, 0))
+        .with_unmet_requirement_callback(record);
+    assert!(matches!(
+        plugin_mgr.load_mock(Box::new(HostBoundPlugin)),
+        Err(Error::UnmetRequirement { actual: Some(actual), .. }) if actual == Version::new(1, 3, 0)
+    ));
+    assert_eq!(
+        *SKIPPED.lock().unwrap(),
+        [("host-bound".to_string(), Some(Version::new(1, 3, 0)))]
+    );
+    let mut plugin_mgr = PluginManager::new_with_host_version(Version::new(1, 5, 2));
+    plugin_mgr.load_mock(Box::new(HostBoundPlugin)).unwrap();
+}
+
+/// The test key and a package named `name` version 1.0.0 around `library` exported with
+/// it, `tweak` adjusts the metadata.
+#[cfg(test)]
+fn signed_package(
+    name: &str,
+    library: &[u8],
+    tweak: impl FnOnce(plugin_defs::PackageMetadataBuilder) -> plugin_defs::PackageMetadataBuilder,
+) -> (ed25519_dalek::SigningKey, Vec<u8>) {
+    use plugin_defs::ExportAlgorithm;
+
+    let signer = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    let metadata = tweak(
+        PackageMetadata::builder()
+            .name(name)
+            .version(Version::new(1, 0, 0)),
+    )
+    .build()
+    .unwrap();
+    let package = Package::new(metadata, library.to_vec());
+    let exported = package.export_with(&signer, ExportAlgorithm::None).unwrap();
+    (signer, exported)
+}
+
+#[cfg(test)]
+fn import(signer: &ed25519_dalek::SigningKey, exported: &[u8]) -> Package {
+    Package::import(exported, &signer.verifying_key()).unwrap()
+}
+
+#[test]
+fn test_revoked_package_is_rejected() {
+    // never opened, the revocation check comes first
+    let (signer, exported) = signed_package("revoked", b"not a library", |b| b);
+    let package = import(&signer, &exported);
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(signer.verifying_key())
+        .with_revocation(HashSet::from([package.digest()]));
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_bytes(&exported) },
+        Err(Error::Revoked(digest)) if digest == package.digest()
+    ));
+}
+
+#[test]
+fn test_pinned_fingerprint_mismatch() {
+    let (signer, pinned) = signed_package("spider", b"pinned build", |b| b);
+    let pinned = import(&signer, &pinned);
+    let (_, exported) = signed_package("spider", b"other build", |b| b);
+    let other = import(&signer, &exported);
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(signer.verifying_key())
+        .with_pinned_fingerprints(HashMap::from([("Spider".to_string(), pinned.digest())]));
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_bytes(&exported) },
+        Err(Error::FingerprintMismatch { expected, actual, .. })
+            if expected == pinned.digest() && actual == other.digest()
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_lazy_binding_keeps_other_flags() {
+    let plugin_mgr = PluginManager::new();
+    assert_eq!(plugin_mgr.options.dlopen_flags, RTLD_LOCAL | RTLD_NOW);
+
+    let plugin_mgr = plugin_mgr
+        .with_dlopen_flags(RTLD_GLOBAL | RTLD_NOW)
+        .with_lazy_binding(true);
+    assert_eq!(plugin_mgr.options.dlopen_flags, RTLD_GLOBAL | RTLD_LAZY);
+    let plugin_mgr = plugin_mgr.with_lazy_binding(false);
+    assert_eq!(plugin_mgr.options.dlopen_flags, RTLD_GLOBAL | RTLD_NOW);
+}
+
+#[test]
+fn test_load_from_lock_requires_locked_build() {
+    let (signer, locked) = signed_package("spider", b"locked build", |b| b);
+    let locked = import(&signer, &locked);
+    let (_, exported) = signed_package("spider", b"other build", |b| b);
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("spider.cdp"), exported).unwrap();
+
+    let mut lock = PluginLock::default();
+    lock.insert(&locked.metadata);
+    let mut plugin_mgr = PluginManager::new().with_verifier(signer.verifying_key());
+    assert!(matches!(
+        unsafe { plugin_mgr.load_from_lock(&lock, dir.path()) },
+        Err(Error::LockMismatch(name)) if name == "spider"
+    ));
+    assert!(plugin_mgr.get_plugin("spider").is_none());
+}
+
+#[test]
+fn test_load_from_lock_reports_rejected_build() {
+    let (signer, exported) = signed_package("spider", b"locked build", |b| b);
+    let locked = import(&signer, &exported);
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("spider.cdp"), exported).unwrap();
+
+    let mut lock = PluginLock::default();
+    lock.insert(&locked.metadata);
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(signer.verifying_key())
+        .with_revocation(HashSet::from([locked.digest()]));
+    assert!(matches!(
+        unsafe { plugin_mgr.load_from_lock(&lock, dir.path()) },
+        Err(Error::Revoked(digest)) if digest == locked.digest()
+    ));
+    assert!(plugin_mgr.get_plugin("spider").is_none());
+}
+
+#[cfg(test)]
+struct VersionedPlugin {
+    name: &'static str,
+    interfaces: &'static [&'static str],
+}
+
+#[cfg(test)]
+impl Plugin for VersionedPlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str(self.name)
+    }
+
+    fn interfaces(&self) -> RVec<RString> {
+        self.interfaces.iter().map(|&i| RString::from(i)).collect()
+    }
+}
+
+#[cfg(test)]
+struct EventPlugin {
+    name: &'static str,
+    received: Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+}
+
+#[cfg(test)]
+impl Plugin for EventPlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str(self.name)
+    }
+
+    fn on_event(&self, event: RStr<'_>, payload: RSlice<'_, u8>) -> RResult<(), PluginError> {
+        if event.as_str() != "url.discovered" {
+            return RErr(PluginError::from("unknown event"));
+        }
+        self.received.lock().unwrap().push(payload.to_vec());
+        ROk(())
+    }
+}
+
+#[cfg(test)]
+struct ReconfigurablePlugin {
+    config: Arc<std::sync::Mutex<Option<String>>>,
+}
+
+#[cfg(test)]
+impl Plugin for ReconfigurablePlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str("spider")
+    }
+
+    fn on_reconfigure(&self, config: ROption<RString>) -> RResult<(), PluginError> {
+        *self.config.lock().unwrap() = config.into_option().map(RString::into_string);
+        ROk(())
+    }
+}
+
+#[cfg(test)]
+struct UnhealthyPlugin;
+
+#[cfg(test)]
+impl Plugin for UnhealthyPlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str("stuck")
+    }
+
+    fn health_check(&self) -> RResult<(), PluginError> {
+        RErr(PluginError::Timeout)
+    }
+}
+
+#[test]
+fn test_health_check_all() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "quiet",
+            req: "*",
+        }))
+        .unwrap();
+    plugin_mgr.load_mock(Box::new(UnhealthyPlugin)).unwrap();
+
+    let health = plugin_mgr.health_check_all();
+    assert_eq!(health.len(), 2);
+    assert_eq!(health[0].0, "quiet");
+    assert!(health[0].1.is_ok());
+    assert_eq!(health[1].0, "stuck");
+    assert!(matches!(health[1].1, Err(PluginError::Timeout)));
+}
+
+#[cfg(test)]
+struct SlowUnloadPlugin;
+
+#[cfg(test)]
+impl Plugin for SlowUnloadPlugin {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str("slow")
+    }
+
+    fn on_plugin_unload(&self) -> RResult<(), PluginError> {
+        thread::sleep(Duration::from_millis(500));
+        ROk(())
+    }
+
+    fn preferred_shutdown(&self) -> RDuration {
+        RDuration::from_millis(50)
+    }
+}
+
+#[test]
+fn test_shutdown_timeout() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr.load_mock(Box::new(SlowUnloadPlugin)).unwrap();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "quick",
+            req: "*",
+        }))
+        .unwrap();
+
+    let start = Instant::now();
+    let results = plugin_mgr.shutdown();
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert_eq!(results[0].0, "quick");
+    assert!(results[0].1.is_ok());
+    assert_eq!(results[1].0, "slow");
+    assert!(matches!(results[1].1, Err(PluginError::Timeout)));
+}
+
+#[test]
+fn test_unload_plugin_timeout() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr.load_mock(Box::new(SlowUnloadPlugin)).unwrap();
+
+    let start = Instant::now();
+    assert!(matches!(
+        plugin_mgr.unload_plugin("slow"),
+        Err(PluginError::Timeout)
+    ));
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert!(plugin_mgr.get_plugin("slow").is_none());
+}
+
+#[test]
+fn test_max_plugins() {
+    let mut plugin_mgr = PluginManager::new().with_max_plugins(1);
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "first",
+            req: "*",
+        }))
+        .unwrap();
+    let result = plugin_mgr.load_mock(Box::new(MockPlugin {
+        name: "second",
+        req: "*",
+    }));
+    assert!(matches!(
+        result,
+        Err(Error::CapacityExceeded {
+            limit: 1,
+            loaded: 1
+        })
+    ));
+    assert!(plugin_mgr.get_plugin("second").is_none());
+}
+
+#[test]
+fn test_reconfigure() {
+    let config = Arc::new(std::sync::Mutex::new(None));
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr
+        .load_mock(Box::new(ReconfigurablePlugin {
+            config: config.clone(),
+        }))
+        .unwrap();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "quiet",
+            req: "*",
+        }))
+        .unwrap();
+
+    plugin_mgr
+        .reconfigure("spider", r#"{"depth": 2}"#.to_string())
+        .unwrap();
+    assert_eq!(config.lock().unwrap().as_deref(), Some(r#"{"depth": 2}"#));
+    assert!(plugin_mgr.reconfigure("quiet", "{}".to_string()).is_err());
+    assert!(plugin_mgr.reconfigure("missing", "{}".to_string()).is_err());
+}
+
+#[test]
+fn test_broadcast_collects_errors() {
+    let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr
+        .load_mock(Box::new(EventPlugin {
+            name: "spider",
+            received: received.clone(),
+        }))
+        .unwrap();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "quiet",
+            req: "*",
+        }))
+        .unwrap();
+
+    assert!(plugin_mgr
+        .broadcast("url.discovered", b"https://example.com")
+        .is_empty());
+    assert_eq!(*received.lock().unwrap(), [b"https://example.com".to_vec()]);
+
+    let errors = plugin_mgr.broadcast("shutdown", b"");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "spider");
+}
+
+#[test]
+fn test_negotiate_interfaces() {
+    let mut plugin_mgr = PluginManager::new();
+    for (name, interfaces) in [
+        ("old", &["comet.spider.v1"][..]),
+        ("new", &["comet.spider.v1", "comet.spider.v2"][..]),
+    ] {
+        plugin_mgr
+            .load_mock(Box::new(VersionedPlugin { name, interfaces }))
+            .unwrap();
+    }
+    let counts = plugin_mgr.common_interfaces();
+    assert_eq!(counts["comet.spider.v1"], 2);
+    assert_eq!(counts["comet.spider.v2"], 1);
+
+    assert_eq!(
+        plugin_mgr.negotiate(&["comet.spider.v2", "comet.spider.v1"]),
+        Some("comet.spider.v1")
+    );
+    assert_eq!(
+        plugin_mgr.negotiate(&["comet.spider.v3", "comet.spider.v2"]),
+        Some("comet.spider.v2")
+    );
+    assert_eq!(plugin_mgr.negotiate(&["comet.spider.v3"]), None);
+}
+
+#[test]
+fn test_target_mismatch_is_rejected() {
+    let (signer, exported) = signed_package("foreign", b"not a library", |b| {
+        b.target("riscv64gc-unknown-none-elf")
+    });
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(signer.verifying_key());
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_bytes(&exported) },
+        Err(Error::TargetMismatch { expected, .. }) if expected == "riscv64gc-unknown-none-elf"
+    ));
+}
+
+#[test]
+fn test_wrong_platform_is_rejected() {
+    // a library of whatever format this host doesn't load
+    let library: &[u8] = if LibraryFormat::native() == Some(LibraryFormat::Pe) {
+        b"\x7fELF not really"
+    } else {
+        b"MZ not really"
+    };
+    let (signer, exported) = signed_package("foreign", library, |b| b);
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(signer.verifying_key());
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_bytes(&exported) },
+        Err(Error::WrongPlatform { host, .. }) if Some(host) == LibraryFormat::native()
+    ));
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn test_library_load_error_names_the_released_file() {
+    // passes the platform check, but dlopen refuses it
+    let (signer, exported) = signed_package("broken", b"\x7fELF not really", |b| b);
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(signer.verifying_key());
+    let result = unsafe { plugin_mgr.load_plugin_bytes(&exported) };
+    assert!(matches!(
+        result,
+        Err(Error::LibraryLoad { name, path, .. })
+            if name == "broken" && path.extension() == Some(OsStr::new("so"))
+    ));
+}
+
+#[test]
+fn test_failed_reload_keeps_old_plugins() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "old",
+            req: "^0.2",
+        }))
+        .unwrap();
+    let paths = [PathBuf::from("does-not-exist.cdp")];
+    assert!(unsafe { plugin_mgr.reload_all(&paths) }.is_err());
+    assert!(plugin_mgr.get_plugin("old").is_some());
+}
+
+#[test]
+fn test_load_filter_rejects_before_dlopen() {
+    // never opened, the filter runs first
+    let (signer, exported) = signed_package("unlisted", b"not a library", |b| b);
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(signer.verifying_key())
+        .with_load_filter(|metadata| match metadata.name.as_str() {
+            "spider" => Ok(()),
+            name => Err(format!("{name} is not on the allowlist")),
+        });
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_bytes(&exported) },
+        Err(Error::Rejected(reason)) if reason == "unlisted is not on the allowlist"
+    ));
+}
+
+#[cfg(test)]
+static UNLOADED: std::sync::Mutex<Vec<&str>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(test)]
+struct UnloadRecorder(&'static str);
+
+#[cfg(test)]
+impl Plugin for UnloadRecorder {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str(self.0)
+    }
+
+    fn on_plugin_unload(&self) -> RResult<(), PluginError> {
+        UNLOADED.lock().unwrap().push(self.0);
+        ROk(())
+    }
+}
+
+#[cfg(test)]
+struct FailingUnload;
+
+#[cfg(test)]
+impl Plugin for FailingUnload {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str("failing")
+    }
+
+    fn on_plugin_unload(&self) -> RResult<(), PluginError> {
+        RErr(PluginError::from("state not persisted"))
+    }
+}
+
+#[test]
+fn test_shutdown_reports_unload_errors() {
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr.load_mock(Box::new(FailingUnload)).unwrap();
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "clean",
+            req: "*",
+        }))
+        .unwrap();
+
+    let results = plugin_mgr.shutdown();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, "clean");
+    assert!(results[0].1.is_ok());
+    assert_eq!(results[1].0, "failing");
+    assert!(matches!(
+        &results[1].1,
+        Err(PluginError::Custom { message, .. }) if message == "state not persisted"
+    ));
+}
+
+#[test]
+fn test_plugin_id() {
+    fn admit(plugin_mgr: &mut PluginManager, id: &str, depends_on: Option<&str>) -> Result<()> {
+        let mut builder = PackageMetadata::builder()
+            .name("spider")
+            .version(Version::new(1, 0, 0))
+            .id(id);
+        if let Some(dep) = depends_on {
+            builder = builder.dependency_with_id("spider", dep, VersionReq::STAR);
+        }
+        let metadata = builder.build().unwrap();
+        plugin_mgr.admit_plugin(
+            Box::new(VersionedPlugin {
+                name: "spider",
+                interfaces: &[],
+            }),
+            Some(metadata),
+            PluginSource::Bytes,
+            None,
+            None,
+        )
+    }
+
+    let mut plugin_mgr = PluginManager::new();
+    admit(
+        &mut plugin_mgr,
+        "com.example.spider",
+        Some("org.other.spider"),
+    )
+    .unwrap();
+    admit(&mut plugin_mgr, "org.other.spider", None).unwrap();
+    assert!(matches!(
+        admit(&mut plugin_mgr, "com.example.spider", None),
+        Err(Error::DuplicatePlugin(id)) if id == "com.example.spider"
+    ));
+    let ids = plugin_mgr.plugin_ids("Spider");
+    assert_eq!(
+        ids,
+        [
+            &PluginId::new("com.example.spider"),
+            &PluginId::new("org.other.spider")
+        ]
+    );
+    let other = PluginId::new("org.other.spider");
+    assert!(plugin_mgr.get_plugin(&other).is_some());
+    // a shared name resolves to neither
+    assert!(plugin_mgr.get_plugin("spider").is_none());
+    assert!(matches!(
+        unsafe { plugin_mgr.get_symbol::<fn()>("spider", b"_comet_plugin_create") },
+        Err(Error::AmbiguousName(name)) if name == "spider"
+    ));
+    assert!(plugin_mgr.reconfigure("spider", "{}".to_string()).is_err());
+
+    // dependencies resolve by id, only the other vendor's spider is depended on
+    assert!(plugin_mgr.unload_plugin(&other).is_err());
+    let example = PluginId::new("com.example.spider");
+    plugin_mgr.unload_plugin(&example).unwrap();
+    assert!(plugin_mgr.get_plugin(&example).is_none());
+    assert!(plugin_mgr.get_plugin("spider").is_some());
+    assert!(plugin_mgr.unload_plugin(&example).is_err());
+
+    // without an id the name is the identity
+    plugin_mgr
+        .load_mock(Box::new(MockPlugin {
+            name: "quiet",
+            req: "*",
+        }))
+        .unwrap();
+    assert_eq!(plugin_mgr.plugin_ids("quiet"), [&PluginId::new("quiet")]);
+}
+
+#[test]
+fn test_name_mismatch() {
+    let mut plugin_mgr = PluginManager::new();
+    let metadata = PackageMetadata::builder()
+        .name("storage")
+        .version(Version::new(1, 0, 0))
+        .build()
+        .unwrap();
+    let result = plugin_mgr.admit_plugin(
+        Box::new(VersionedPlugin {
+            name: "spider",
+            interfaces: &[],
+        }),
+        Some(metadata.clone()),
+        PluginSource::Bytes,
+        None,
+        None,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::NameMismatch { metadata, runtime }) if metadata == "storage" && runtime == "spider"
+    ));
+    assert!(plugin_mgr.get_plugin("spider").is_none());
+
+    // names are compared like everywhere else
+    plugin_mgr
+        .admit_plugin(
+            Box::new(VersionedPlugin {
+                name: "Storage",
+                interfaces: &[],
+            }),
+            Some(metadata),
+            PluginSource::Bytes,
+            None,
+            None,
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_dispatch_by_priority() {
+    let mut plugin_mgr = PluginManager::new();
+    for (name, priority) in [("process", 0), ("filter", 10), ("store", 0), ("audit", -5)] {
+        let metadata = PackageMetadata::builder()
+            .name(name)
+            .version(Version::new(1, 0, 0))
+            .priority(priority)
+            .build()
+            .unwrap();
+        plugin_mgr
+            .admit_plugin(
+                Box::new(VersionedPlugin {
+                    name,
+                    interfaces: &["comet.spider.v1"],
+                }),
+                Some(metadata),
+                PluginSource::Mock,
+                None,
+                None,
+            )
+            .unwrap();
+    }
+    let names: Vec<_> = plugin_mgr
+        .plugins_with_interface("comet.spider.v1")
+        .iter()
+        .map(|plugin| plugin.name().to_string())
+        .collect();
+    assert_eq!(names, ["filter", "process", "store", "audit"]);
+}
+
+#[test]
+fn test_unload_in_dependency_order() {
+    let metadata = |name: &str, dependencies: &[&str]| {
+        let mut builder = PackageMetadata::builder()
+            .name(name)
+            .version(Version::new(1, 0, 0));
+        for dep in dependencies {
+            builder = builder.dependency(*dep, VersionReq::STAR);
+        }
+        builder.build().unwrap()
+    };
+    let mut plugin_mgr = PluginManager::new();
+    // A is loaded first, so plain reverse load order would unload B before A
+    plugin_mgr
+        .admit_plugin(
+            Box::new(UnloadRecorder("a")),
+            Some(metadata("a", &["B"])),
+            PluginSource::Mock,
+            None,
+            None,
+        )
+        .unwrap();
+    plugin_mgr
+        .admit_plugin(
+            Box::new(UnloadRecorder("b")),
+            Some(metadata("b", &[])),
+            PluginSource::Mock,
+            None,
+            None,
+        )
+        .unwrap();
+    let dependencies = plugin_mgr.dependencies("A").unwrap();
+    assert_eq!(dependencies.len(), 1);
+    assert_eq!(dependencies[0].name, "B");
+    assert_eq!(plugin_mgr.dependencies("b"), Some(&[][..]));
+    assert_eq!(plugin_mgr.dependencies("c"), None);
+
+    drop(plugin_mgr);
+    assert_eq!(*UNLOADED.lock().unwrap(), ["a", "b"]);
+}
+
+#[test]
+fn test_verifier_fingerprint() {
+    assert_eq!(
+        PluginManager::new().verifier_fingerprint(),
+        verifier_fingerprint()
+    );
+    let signer = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    let plugin_mgr = PluginManager::new().with_verifier(signer.verifying_key());
+    assert_eq!(
+        plugin_mgr.verifier_fingerprint(),
+        Some(plugin_defs::fingerprint(signer.verifying_key().as_bytes()))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_replace_closes_the_old_library() {
+    use plugin_defs::Package;
+
+    // the process itself stands in for the plugin libraries
+    let this = || Some(Library::from(libloading::os::unix::Library::this()));
+    let admit = |plugin_mgr: &mut PluginManager, package: &Package, library| {
+        plugin_mgr.admit_plugin(
+            Box::new(VersionedPlugin {
+                name: "spider",
+                interfaces: &[],
+            }),
+            Some(package.metadata.clone()),
+            PluginSource::Bytes,
+            Some(library),
+            None,
+        )
+    };
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .build()
+        .unwrap();
+    let package = Package::new(metadata, vec![0xcd; 16]);
+    let mut plugin_mgr = PluginManager::new();
+    plugin_mgr.loaded_libraries.push(this());
+    admit(&mut plugin_mgr, &package, 0).unwrap();
+
+    // the same build is not loaded twice
+    assert!(matches!(
+        plugin_mgr.admission(&package, false),
+        Ok(Admission::Loaded(0))
+    ));
+
+    // a failed replacement puts the old plugin back
+    let admission = plugin_mgr.admission(&package, true).unwrap();
+    assert!(plugin_mgr.get_plugin("spider").is_none());
+    let failed = Err(Error::MissingSymbol("_comet_plugin_create".to_string()));
+    assert!(plugin_mgr.settle(failed, admission).is_err());
+    assert_eq!(plugin_mgr.plugin_ids("spider").len(), 1);
+
+    let admission = plugin_mgr.admission(&package, true).unwrap();
+    plugin_mgr.loaded_libraries.push(this());
+    let result = admit(&mut plugin_mgr, &package, 1);
+    assert_eq!(plugin_mgr.settle(result, admission).unwrap(), 0);
+    assert_eq!(plugin_mgr.plugin_ids("spider").len(), 1);
+    assert!(plugin_mgr.loaded_libraries[0].is_none());
+    assert!(plugin_mgr.loaded_libraries[1].is_some());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_incompatible_build_is_not_constructed() {
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .plugin_base_version(Version::new(0, 1, 0))
+        .build()
+        .unwrap();
+    // the process exports no constructor, so a lookup would fail with `MissingSymbol`
+    let opened = OpenedLibrary {
+        metadata,
+        library: Library::from(libloading::os::unix::Library::this()),
+        resources: None,
+    };
+    let mut plugin_mgr = PluginManager::new();
+    assert!(matches!(
+        unsafe { plugin_mgr.register_library(opened, PluginSource::Bytes, None) },
+        Err(Error::UnmetRequirement { req, .. }) if req == "^0.1.0"
+    ));
+    assert!(plugin_mgr.loaded_libraries.is_empty());
 }
@@ -0,0 +1,458 @@
+use crate::log::*;
+use crate::{
+    canonical_name, satisfies, utils, Error, LoadFilter, LoadPhase, LoadStrategy, LoadTimings,
+    PluginId, ProgressCallback, Result, SymbolPolicy,
+};
+use ed25519_dalek::VerifyingKey;
+use libloading::Library;
+use plugin_commons::consts::TARGET;
+use plugin_defs::{Dictionary, Digest, LibraryFormat, Package, PackageMetadata};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use tempfile::TempDir;
+
+/// Everything that controls how a package is turned into an opened library.
+#[derive(Clone)]
+pub(crate) struct LoaderConfig {
+    pub strategy: LoadStrategy,
+    pub verifier: Option<VerifyingKey>,
+    pub progress: Option<ProgressCallback>,
+    /// digests of libraries that must not be loaded even if validly signed
+    pub revoked: Arc<HashSet<Digest>>,
+    /// expected library digests by canonical plugin name
+    pub pinned: Arc<HashMap<String, Digest>>,
+    /// zstd dictionaries packages may have been compressed with
+    pub dictionaries: Arc<Vec<Dictionary>>,
+    pub filter: Option<LoadFilter>,
+    /// flags the library is opened with, ignored on Windows
+    pub dlopen_flags: i32,
+    /// how long the plugin constructor may run, `None` waits forever
+    pub load_timeout: Option<Duration>,
+    /// symbols the library must and must not export, checked before it is opened
+    pub symbol_policy: Option<SymbolPolicy>,
+}
+
+impl LoaderConfig {
+    pub fn report(&self, phase: LoadPhase, done: u64, total: u64) {
+        if let Some(progress) = self.progress {
+            progress(phase, done, total)
+        }
+    }
+}
+
+/// A library opened by the loader, with the metadata of its package.
+pub(crate) struct OpenedLibrary {
+    pub metadata: PackageMetadata,
+    pub library: Library,
+    /// temp dir holding the library and its resources, kept as long as the plugin
+    /// if the package has resources
+    pub resources: Option<TempDir>,
+}
+
+/// Read a package file and open its library, see [`open_library_bytes`].
+///
+/// # Safety
+/// this api is sound iff when the package is a valid plugin package.
+pub(crate) unsafe fn open_library<P: AsRef<Path>>(
+    filename: P,
+    options: &LoaderConfig,
+    timings: &mut LoadTimings,
+) -> Result<OpenedLibrary> {
+    let content = read_package(filename)?;
+    open_library_bytes(&content, options, timings)
+}
+
+/// Read a package file into memory.
+pub(crate) fn read_package<P: AsRef<Path>>(filename: P) -> Result<Vec<u8>> {
+    trace!("loading package: {:?}", filename.as_ref());
+    Ok(fs::read(filename).map_err(plugin_defs::Error::UnableToReadPackage)?)
+}
+
+/// Verify and release the library of a package, then open it according to the strategy.
+/// Returns the package metadata alongside the opened library.
+///
+/// Only does blocking work and never runs plugin code besides the library initializers,
+/// so it is safe to move to a blocking thread.
+///
+/// # Safety
+/// this api is sound iff when the package is a valid plugin package.
+pub(crate) unsafe fn open_library_bytes(
+    exported: &[u8],
+    options: &LoaderConfig,
+    timings: &mut LoadTimings,
+) -> Result<OpenedLibrary> {
+    let package = import_package(exported, options, timings)?;
+    open_package(package, options, timings)
+}
+
+/// Verify the signature and digest of a serialized package and check it is not revoked.
+pub(crate) fn import_package(
+    exported: &[u8],
+    options: &LoaderConfig,
+    timings: &mut LoadTimings,
+) -> Result<Package> {
+    let verifier = options.verifier.ok_or(Error::NoVerifierKey)?;
+    let package_len = exported.len() as u64;
+    options.report(LoadPhase::Decompressing, 0, package_len);
+    let start = Instant::now();
+    let package = Package::import_with_dictionaries(exported, &verifier, &options.dictionaries)?;
+    timings.decompress = start.elapsed();
+    options.report(LoadPhase::Decompressing, package_len, package_len);
+    // the import already checked the recorded digest against the library
+    if let Some(digest) = package.recorded_digest() {
+        if options.revoked.contains(&digest) {
+            return Err(Error::Revoked(digest));
+        }
+        let name = canonical_name(&package.metadata.name);
+        if let Some(pinned) = options.pinned.get(&name) {
+            if !bool::from(pinned.ct_eq(&digest)) {
+                return Err(Error::FingerprintMismatch {
+                    name: package.metadata.name.clone(),
+                    expected: pinned.clone(),
+                    actual: digest,
+                });
+            }
+        }
+    }
+    // fail before dlopen, which would only report an unreadable file
+    if let Some(target) = &package.metadata.target {
+        if target != TARGET {
+            return Err(Error::TargetMismatch {
+                expected: target.clone(),
+                actual: TARGET.to_string(),
+            });
+        }
+    }
+    // packages without a target still carry a library of the wrong kind
+    if let (Some(detected), Some(host)) = (
+        LibraryFormat::detect(package.library()),
+        LibraryFormat::native(),
+    ) {
+        if detected != host {
+            return Err(Error::WrongPlatform { detected, host });
+        }
+    }
+    if let Some(policy) = &options.symbol_policy {
+        policy.check(&package.metadata.name, package.library())?;
+    }
+    if let Some(filter) = &options.filter {
+        filter(&package.metadata).map_err(Error::Rejected)?;
+    }
+    Ok(package)
+}
+
+/// Release the library of a package returned by [`import_package`] and open it.
+///
+/// # Safety
+/// this api is sound iff when the package is a valid plugin package.
+pub(crate) unsafe fn open_package(
+    package: Package,
+    options: &LoaderConfig,
+    timings: &mut LoadTimings,
+) -> Result<OpenedLibrary> {
+    open_package_in(package, std::env::temp_dir(), options, timings)
+}
+
+/// Like [`open_package`], releasing the library under `parent`.
+///
+/// # Safety
+/// this api is sound iff when the package is a valid plugin package.
+pub(crate) unsafe fn open_package_in<P: AsRef<Path>>(
+    package: Package,
+    parent: P,
+    options: &LoaderConfig,
+    timings: &mut LoadTimings,
+) -> Result<OpenedLibrary> {
+    let library_len = package.library_len() as u64;
+    // the lock taken by the recheck is held until dlopen returns, so the checked file
+    // is the one opened
+    let (temp_dir, lib_path, _lock) = release_package(&package, parent, options, timings)?;
+    options.report(LoadPhase::Linking, 0, library_len);
+    let start = Instant::now();
+    let lib = utils::open_library(&lib_path, options.dlopen_flags).map_err(|source| {
+        Error::LibraryLoad {
+            name: package.metadata.name.clone(),
+            path: lib_path.clone(),
+            source,
+        }
+    })?;
+    timings.dlopen = start.elapsed();
+    options.report(LoadPhase::Linking, library_len, library_len);
+    // without resources the library file is not needed once opened
+    let resources = (!package.resources.is_empty()).then_some(temp_dir);
+    Ok(OpenedLibrary {
+        metadata: package.metadata,
+        library: lib,
+        resources,
+    })
+}
+
+/// Extract the library and resources of a package into a new temp dir under `parent`,
+/// then lock and validate the library according to the strategy.
+///
+/// With [`LoadStrategy::ReleaseRecheck`] the locked library file is returned, the lock
+/// lasts as long as it. Any failure drops the temp dir, so nothing is left behind that
+/// was not validated.
+pub(crate) fn release_package<P: AsRef<Path>>(
+    package: &Package,
+    parent: P,
+    options: &LoaderConfig,
+    timings: &mut LoadTimings,
+) -> Result<(TempDir, PathBuf, Option<File>)> {
+    let library_len = package.library_len() as u64;
+    options.report(LoadPhase::WritingTemp, 0, library_len);
+    let start = Instant::now();
+    let (temp_dir, lib_path) = package.release_lib_to_temp_in(parent)?;
+    package.release_resources_to_temp(temp_dir.path())?;
+    timings.write_temp = start.elapsed();
+    options.report(LoadPhase::WritingTemp, library_len, library_len);
+
+    let lock = if options.strategy == LoadStrategy::ReleaseRecheck {
+        trace!("using release-recheck strategy");
+        trace!("re-open and lockdown dll from: {:?}", lib_path);
+        let start = Instant::now();
+        let mut lib_file = utils::lock_open_file(&lib_path)?;
+        utils::validate_file(
+            &mut lib_file,
+            package.metadata.digest_algorithm,
+            package.library_digest(),
+            |done| options.report(LoadPhase::Hashing, done, library_len),
+        )?;
+        timings.hash = start.elapsed();
+        trace!("integrity check passed");
+        Some(lib_file)
+    } else {
+        trace!("using trust-in-memory strategy");
+        None
+    };
+    Ok((temp_dir, lib_path, lock))
+}
+
+/// Order packages so that each comes after the packages of the set it depends on.
+///
+/// Dependencies are resolved like the manager does, by id if they name one. Dependencies
+/// outside the set are left to the manager, packages without constraints between them
+/// keep their entry order.
+pub(crate) fn dependency_order<T>(packages: Vec<(T, Package)>) -> Result<Vec<(T, Package)>> {
+    let identities: Vec<(String, PluginId)> = packages
+        .iter()
+        .map(|(_, package)| {
+            let key = canonical_name(&package.metadata.name);
+            let id = PluginId::of(Some(&package.metadata), &key);
+            (key, id)
+        })
+        .collect();
+    let dependencies: Vec<Vec<usize>> = packages
+        .iter()
+        .enumerate()
+        .map(|(i, (_, package))| {
+            (0..packages.len())
+                .filter(|&j| {
+                    let (key, id) = &identities[j];
+                    j != i
+                        && package
+                            .metadata
+                            .dependencies
+                            .iter()
+                            .any(|dep| satisfies(dep, key, id))
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut order = Vec::with_capacity(packages.len());
+    let mut done = vec![false; packages.len()];
+    while order.len() < packages.len() {
+        let next =
+            (0..packages.len()).find(|&i| !done[i] && dependencies[i].iter().all(|&dep| done[dep]));
+        let Some(next) = next else {
+            let stuck = (0..packages.len()).find(|&i| !done[i]).unwrap();
+            return Err(Error::DependencyCycle(
+                packages[stuck].1.metadata.name.clone(),
+            ));
+        };
+        done[next] = true;
+        order.push(next);
+    }
+
+    let mut packages: Vec<_> = packages.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|i| packages[i].take().unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) fn package(name: &str, dependencies: &[&str]) -> (String, Package) {
+    let mut builder = plugin_defs::PackageMetadata::builder()
+        .name(name)
+        .version(semver::Version::new(1, 0, 0));
+    for dep in dependencies {
+        builder = builder.dependency(*dep, semver::VersionReq::STAR);
+    }
+    let package = Package::new(builder.build().unwrap(), name.as_bytes().to_vec());
+    (format!("{name}.cdp"), package)
+}
+
+#[cfg(test)]
+fn config(strategy: LoadStrategy) -> LoaderConfig {
+    LoaderConfig {
+        strategy,
+        verifier: None,
+        progress: None,
+        revoked: Default::default(),
+        pinned: Default::default(),
+        dictionaries: Default::default(),
+        filter: None,
+        dlopen_flags: 0,
+        load_timeout: None,
+        symbol_policy: None,
+    }
+}
+
+#[test]
+fn test_dependency_order() {
+    let packages = vec![
+        package("crawler", &["Spider", "storage"]),
+        package("spider", &["external"]),
+        package("storage", &[]),
+    ];
+    let ordered = dependency_order(packages).unwrap();
+    let names: Vec<_> = ordered.iter().map(|(entry, _)| entry.as_str()).collect();
+    assert_eq!(names, ["spider.cdp", "storage.cdp", "crawler.cdp"]);
+
+    let cycle = vec![package("a", &["b"]), package("b", &["a"])];
+    assert!(matches!(
+        dependency_order(cycle),
+        Err(Error::DependencyCycle(_))
+    ));
+
+    // of two spiders, only the one with the named id is depended on
+    let vendored = |entry: &str, id: &str, dependency: Option<&str>| {
+        let mut builder = plugin_defs::PackageMetadata::builder()
+            .name("spider")
+            .version(semver::Version::new(1, 0, 0))
+            .id(id);
+        if let Some(dependency) = dependency {
+            builder = builder.dependency_with_id("spider", dependency, semver::VersionReq::STAR);
+        }
+        (
+            entry.to_string(),
+            Package::new(builder.build().unwrap(), vec![]),
+        )
+    };
+    let packages = vec![
+        vendored(
+            "example.cdp",
+            "com.example.spider",
+            Some("org.other.spider"),
+        ),
+        vendored("other.cdp", "org.other.spider", None),
+    ];
+    let ordered = dependency_order(packages).unwrap();
+    let names: Vec<_> = ordered.iter().map(|(entry, _)| entry.as_str()).collect();
+    assert_eq!(names, ["other.cdp", "example.cdp"]);
+}
+
+#[test]
+fn test_release_failure_leaves_nothing_behind() {
+    let options = config(LoadStrategy::ReleaseRecheck);
+    let parent = tempfile::tempdir().unwrap();
+    let (_, mut package) = package("spider", &[]);
+    let (temp_dir, lib_path, lock) = release_package(
+        &package,
+        parent.path(),
+        &options,
+        &mut LoadTimings::default(),
+    )
+    .unwrap();
+    assert_eq!(fs::read(&lib_path).unwrap(), b"spider");
+    drop(lock);
+    drop(temp_dir);
+    assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 0);
+
+    // the library is already written when the resources fail
+    package.resources.insert("../escape".to_string(), vec![0]);
+    let err = release_package(
+        &package,
+        parent.path(),
+        &options,
+        &mut LoadTimings::default(),
+    );
+    assert!(matches!(
+        err,
+        Err(Error::InvalidPackage(
+            plugin_defs::Error::InvalidResourceName(_)
+        ))
+    ));
+    assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 0);
+
+    let missing = parent.path().join("missing");
+    assert!(release_package(&package, &missing, &options, &mut LoadTimings::default()).is_err());
+    assert!(!missing.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recheck_lock_is_held_during_dlopen() {
+    use rustix::fs::{flock, FlockOperation};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    static PARENT: Mutex<Option<PathBuf>> = Mutex::new(None);
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    fn is_locked(path: &Path) -> bool {
+        let file = File::open(path).unwrap();
+        flock(&file, FlockOperation::NonBlockingLockExclusive).is_err()
+    }
+
+    // reported right before dlopen, probes the library from another open file
+    fn progress(phase: LoadPhase, done: u64, _: u64) {
+        if phase != LoadPhase::Linking || done != 0 {
+            return;
+        }
+        let parent = PARENT.lock().unwrap().clone().unwrap();
+        let temp_dir = fs::read_dir(parent)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let lib_path = fs::read_dir(temp_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        LOCKED.store(is_locked(&lib_path), Ordering::SeqCst);
+    }
+
+    let parent = tempfile::tempdir().unwrap();
+    *PARENT.lock().unwrap() = Some(parent.path().to_path_buf());
+    let options = LoaderConfig {
+        progress: Some(progress),
+        ..config(LoadStrategy::ReleaseRecheck)
+    };
+    let (_, spider) = package("spider", &[]);
+    // not a real library, dlopen fails once the lock has been probed
+    let result =
+        unsafe { open_package_in(spider, parent.path(), &options, &mut LoadTimings::default()) };
+    assert!(matches!(result, Err(Error::LibraryLoad { .. })));
+    assert!(LOCKED.load(Ordering::SeqCst));
+
+    let (_, spider) = package("spider", &[]);
+    let (_temp_dir, lib_path, lock) = release_package(
+        &spider,
+        parent.path(),
+        &config(LoadStrategy::TrustInMemory),
+        &mut LoadTimings::default(),
+    )
+    .unwrap();
+    assert!(lock.is_none());
+    assert!(!is_locked(&lib_path));
+}
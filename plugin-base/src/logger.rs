@@ -1,11 +1,64 @@
-use log::{Log, Metadata, Record};
+use crate::PluginError;
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::{Lazy, OnceCell};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::RwLock;
 
 pub type LogCallback = fn(record: &Record);
 
+thread_local! {
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+static TARGET_LEVELS: Lazy<RwLock<HashMap<String, LevelFilter>>> = Lazy::new(Default::default);
+
+/// Set the max level the host accepts from a plugin, keyed by log target.
+///
+/// Records from plugins carry their module path as target, so `"spider"` covers
+/// `spider` and `spider::*`. The most specific matching target wins.
+pub fn set_plugin_log_level(target: impl Into<String>, level: LevelFilter) {
+    TARGET_LEVELS.write().unwrap().insert(target.into(), level);
+}
+
+fn target_level(target: &str) -> Option<LevelFilter> {
+    let levels = TARGET_LEVELS.read().unwrap();
+    let mut prefix = target;
+    loop {
+        if let Some(level) = levels.get(prefix) {
+            return Some(*level);
+        }
+        prefix = &prefix[..prefix.rfind("::")?];
+    }
+}
+
+/// Forward a plugin record to the host logger.
+///
+/// A record logged while this thread is already forwarding one is dropped: if the host
+/// logger is itself an [`ExternalLogger`] calling back here, this would recurse forever.
 pub(crate) fn log_callback(record: &Record) {
+    if let Some(level) = target_level(record.target()) {
+        if record.level() > level {
+            return;
+        }
+    }
+    if IN_CALLBACK.with(|flag| flag.replace(true)) {
+        return;
+    }
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            IN_CALLBACK.with(|flag| flag.set(false));
+        }
+    }
+    let _reset = Reset;
     log::logger().log(record)
 }
 
+/// The logger a plugin installs to hand its records to the host.
+///
+/// Only for plugins: the host must install its own logger, an `ExternalLogger` as the
+/// host logger would only feed records back to itself, they are dropped.
 pub struct ExternalLogger {
     callback: LogCallback,
 }
@@ -27,3 +80,50 @@ impl Log for ExternalLogger {
 
     fn flush(&self) {}
 }
+
+/// Install an [`ExternalLogger`] forwarding to the host and set the max level.
+///
+/// Safe to call more than once: if this function already installed the logger, only the
+/// level is updated. Fails with [`PluginError::SetLogger`] if some other logger was set.
+pub fn init_plugin_logger(
+    callback: LogCallback,
+    max_level: LevelFilter,
+) -> Result<(), PluginError> {
+    static INSTALLED: OnceCell<bool> = OnceCell::new();
+    let installed = *INSTALLED.get_or_init(|| {
+        let logger: &'static ExternalLogger = Box::leak(Box::new(ExternalLogger::new(callback)));
+        log::set_logger(logger).is_ok()
+    });
+    if !installed {
+        return Err(PluginError::SetLogger);
+    }
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[test]
+fn test_target_level_prefers_most_specific() {
+    set_plugin_log_level("test_spider", LevelFilter::Warn);
+    set_plugin_log_level("test_spider::crawler", LevelFilter::Debug);
+    assert_eq!(target_level("test_spider"), Some(LevelFilter::Warn));
+    assert_eq!(target_level("test_spider::queue"), Some(LevelFilter::Warn));
+    assert_eq!(
+        target_level("test_spider::crawler::http"),
+        Some(LevelFilter::Debug)
+    );
+    assert_eq!(target_level("test_spiders"), None);
+}
+
+#[test]
+fn test_log_callback_does_not_recurse() {
+    // the misconfiguration: the host installs the plugin side of the bridge
+    let _ = log::set_boxed_logger(Box::new(ExternalLogger::new(log_callback)));
+    log::set_max_level(LevelFilter::Trace);
+    log_callback(
+        &Record::builder()
+            .args(format_args!("dropped instead of overflowing the stack"))
+            .target("test_recursion")
+            .build(),
+    );
+    assert!(!IN_CALLBACK.with(Cell::get));
+}
@@ -0,0 +1,67 @@
+//! Panic handling for plugin constructors, used by `declare_plugin!`.
+
+use crate::PluginError;
+use abi_stable::std_types::{RNone, RSome, RString};
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    static LAST_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Chain a panic hook that remembers where the last panic on this thread happened.
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(location) = info.location() {
+                LAST_LOCATION.with(|l| *l.borrow_mut() = Some(location.to_string()));
+            }
+            previous(info)
+        }));
+    });
+}
+
+/// Run a plugin constructor, turning a panic into [`PluginError::Custom`]
+/// carrying the panic message and location instead of unwinding across the FFI boundary.
+pub fn catch_constructor_panic<T>(
+    f: impl FnOnce() -> Result<T, PluginError>,
+) -> Result<T, PluginError> {
+    install_hook();
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "plugin constructor panicked".to_string());
+            let source_location = match LAST_LOCATION.with(|l| l.borrow_mut().take()) {
+                Some(location) => RSome(RString::from(location)),
+                None => RNone,
+            };
+            Err(PluginError::Custom {
+                message: RString::from(message),
+                source_location,
+            })
+        }
+    }
+}
+
+#[test]
+fn test_catch_constructor_panic_records_location() {
+    let result = catch_constructor_panic::<()>(|| panic!("boom"));
+    match result {
+        Err(PluginError::Custom {
+            message,
+            source_location: RSome(location),
+        }) => {
+            assert_eq!(message.as_str(), "boom");
+            assert!(location.as_str().contains("panic.rs"));
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
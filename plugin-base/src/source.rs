@@ -0,0 +1,161 @@
+//! Where [`PluginManager::load_plugin_by_name`](crate::PluginManager::load_plugin_by_name)
+//! gets packages from.
+use crate::log::*;
+use crate::{canonical_name, Error, Result};
+use plugin_defs::Package;
+use semver::{Version, VersionReq};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+/// A registry of serialized packages, looked up by plugin name and version.
+pub trait PackageSource: Send + Sync {
+    /// The serialized package of plugin `name` in a version matching `req`, or
+    /// [`Error::PackageNotFound`]. The manager verifies it like any other package.
+    fn fetch(&self, name: &str, req: &VersionReq) -> Result<Vec<u8>>;
+}
+
+/// The packages of a directory, the highest matching version wins.
+///
+/// The metadata is read without verifying the signature, packages compressed with a
+/// dictionary are skipped, like files that do not decode as packages.
+#[derive(Clone, Debug)]
+pub struct DirectorySource {
+    dir: PathBuf,
+    extension: Option<String>,
+}
+
+impl DirectorySource {
+    /// Packages with the `cdp` extension in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DirectorySource {
+            dir: dir.into(),
+            extension: Some("cdp".to_string()),
+        }
+    }
+
+    /// Consider every file in the directory, skipping the ones that are not packages.
+    pub fn with_any_extension(mut self) -> Self {
+        self.extension = None;
+        self
+    }
+}
+
+impl PackageSource for DirectorySource {
+    fn fetch(&self, name: &str, req: &VersionReq) -> Result<Vec<u8>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(extension) = &self.extension {
+                if path.extension() != Some(OsStr::new(extension)) {
+                    continue;
+                }
+            }
+            paths.push(path);
+        }
+        // the first file in name order wins between equal versions
+        paths.sort();
+
+        let key = canonical_name(name);
+        let mut best: Option<(Version, Vec<u8>)> = None;
+        for path in paths {
+            let exported = fs::read(&path)?;
+            let metadata = match Package::peek_metadata(&exported) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    debug!("skip {:?}, not a readable package: {}", path, e);
+                    continue;
+                }
+            };
+            if canonical_name(&metadata.name) != key || !req.matches(&metadata.version) {
+                continue;
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(version, _)| metadata.version > *version)
+            {
+                best = Some((metadata.version, exported));
+            }
+        }
+        best.map(|(_, exported)| exported)
+            .ok_or_else(|| Error::PackageNotFound {
+                name: name.to_string(),
+                req: req.to_string(),
+            })
+    }
+}
+
+#[test]
+fn test_directory_source() {
+    use plugin_defs::{ExportAlgorithm, PackageMetadata};
+
+    let signer = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    let dir = tempfile::tempdir().unwrap();
+    for (file, name, version) in [
+        ("a.cdp", "spider", Version::new(1, 0, 0)),
+        ("b.cdp", "spider", Version::new(1, 2, 0)),
+        ("c.cdp", "spider", Version::new(2, 0, 0)),
+        ("d.cdp", "storage", Version::new(1, 5, 0)),
+    ] {
+        let metadata = PackageMetadata::builder()
+            .name(name)
+            .version(version)
+            .build()
+            .unwrap();
+        let exported = Package::new(metadata, file.as_bytes().to_vec())
+            .export_with(&signer, ExportAlgorithm::None)
+            .unwrap();
+        fs::write(dir.path().join(file), exported).unwrap();
+    }
+    fs::write(dir.path().join("junk.cdp"), b"not a package").unwrap();
+
+    let source = DirectorySource::new(dir.path());
+    let exported = source
+        .fetch("Spider", &VersionReq::parse("^1").unwrap())
+        .unwrap();
+    let metadata = Package::peek_metadata(&exported).unwrap();
+    assert_eq!(metadata.version, Version::new(1, 2, 0));
+
+    assert!(matches!(
+        source.fetch("storage", &VersionReq::parse("^2").unwrap()),
+        Err(Error::PackageNotFound { name, .. }) if name == "storage"
+    ));
+}
+
+#[test]
+fn test_directory_source_skips_crafted_package() {
+    use ed25519_dalek::Signer;
+    use plugin_defs::{
+        ExportAlgorithm, PackageExport, PackageMetadata, SignatureScheme, FORMAT_VERSION,
+    };
+
+    let signer = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    let dir = tempfile::tempdir().unwrap();
+    // the first field claims a 1 TiB string, the payload ends right after
+    let mut payload = (1u64 << 40).to_le_bytes().to_vec();
+    payload.extend_from_slice(&[0xcd; 64]);
+    let crafted = PackageExport {
+        alog: ExportAlgorithm::Zstd,
+        payload: ExportAlgorithm::Zstd.encode(&payload).unwrap(),
+        signature: signer.sign(&payload),
+        scheme: SignatureScheme::Ed25519,
+        format_version: FORMAT_VERSION,
+    };
+    fs::write(dir.path().join("a.cdp"), crafted.to_bytes().unwrap()).unwrap();
+
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .build()
+        .unwrap();
+    let exported = Package::new(metadata, vec![0xcd; 64])
+        .export(&signer)
+        .unwrap();
+    fs::write(dir.path().join("b.cdp"), &exported).unwrap();
+
+    let source = DirectorySource::new(dir.path());
+    assert_eq!(source.fetch("spider", &VersionReq::STAR).unwrap(), exported);
+}
@@ -0,0 +1,97 @@
+//! Checks on the symbols a plugin library exports, made on the verified library before
+//! it is opened, so a plugin cannot interpose host symbols such as `malloc`.
+use crate::{Error, Result};
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::STB_LOCAL;
+use goblin::mach::Mach;
+use goblin::Object;
+use std::collections::BTreeSet;
+
+/// Symbols a plugin library must and must not export, see
+/// [`PluginManager::with_symbol_policy`](crate::PluginManager::with_symbol_policy).
+///
+/// Names are given without the leading underscore Mach-O adds to C symbols.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymbolPolicy {
+    required: BTreeSet<String>,
+    denied: BTreeSet<String>,
+}
+
+impl SymbolPolicy {
+    /// A policy requiring the plugin constructor `_comet_plugin_create` and denying nothing.
+    pub fn new() -> Self {
+        Self::default().require("_comet_plugin_create")
+    }
+
+    /// Reject libraries not exporting `symbol`.
+    pub fn require(mut self, symbol: impl Into<String>) -> Self {
+        self.required.insert(symbol.into());
+        self
+    }
+
+    /// Reject libraries exporting `symbol`.
+    pub fn deny(mut self, symbol: impl Into<String>) -> Self {
+        self.denied.insert(symbol.into());
+        self
+    }
+
+    /// Check the exports of the library of plugin `name`.
+    pub(crate) fn check(&self, name: &str, library: &[u8]) -> Result<()> {
+        let exports = exported_symbols(library).map_err(|e| Error::UnreadableExports {
+            name: name.to_string(),
+            reason: e.to_string(),
+        })?;
+        if let Some(symbol) = self.required.difference(&exports).next() {
+            return Err(Error::MissingSymbol(symbol.clone()));
+        }
+        if let Some(symbol) = self.denied.intersection(&exports).next() {
+            return Err(Error::DeniedSymbol {
+                name: name.to_string(),
+                symbol: symbol.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Names of the symbols defined and exported by an ELF, Mach-O or PE shared library.
+pub fn exported_symbols(library: &[u8]) -> goblin::error::Result<BTreeSet<String>> {
+    let exports = match Object::parse(library)? {
+        Object::Elf(elf) => elf
+            .dynsyms
+            .iter()
+            .filter(|sym| sym.st_shndx != SHN_UNDEF as usize && sym.st_bind() != STB_LOCAL)
+            .filter_map(|sym| elf.dynstrtab.get_at(sym.st_name))
+            .map(String::from)
+            .collect(),
+        Object::Mach(Mach::Binary(macho)) => macho
+            .exports()?
+            .into_iter()
+            .map(|export| match export.name.strip_prefix('_') {
+                Some(name) => name.to_string(),
+                None => export.name,
+            })
+            .collect(),
+        Object::PE(pe) => pe
+            .exports
+            .iter()
+            .filter_map(|export| export.name)
+            .map(String::from)
+            .collect(),
+        _ => {
+            return Err(goblin::error::Error::Malformed(
+                "not a single-architecture shared library".to_string(),
+            ))
+        }
+    };
+    Ok(exports)
+}
+
+#[test]
+fn test_unreadable_exports() {
+    let policy = SymbolPolicy::new().deny("malloc");
+    assert!(matches!(
+        policy.check("spider", b"not a library"),
+        Err(Error::UnreadableExports { name, .. }) if name == "spider"
+    ));
+}
@@ -1,8 +1,11 @@
 use crate::log::*;
 use crate::{Error, Result};
+use libloading::Library;
+use plugin_defs::{Digest, DigestAlgorithm};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use subtle::ConstantTimeEq;
 
 #[cfg(windows)]
 pub fn lock_open_file(path: impl AsRef<Path>) -> Result<File> {
@@ -27,14 +30,45 @@ pub fn lock_open_file(path: impl AsRef<Path>) -> Result<File> {
     Ok(f)
 }
 
-pub fn validate_file(f: &mut File, expect_digest: [u8; 64]) -> Result<()> {
+/// Open a released library with explicit `dlopen` flags.
+#[cfg(unix)]
+pub unsafe fn open_library(
+    path: impl AsRef<Path>,
+    flags: i32,
+) -> Result<Library, libloading::Error> {
+    let lib = libloading::os::unix::Library::open(Some(path.as_ref()), flags)?;
+    Ok(lib.into())
+}
+
+/// Open a released library, `LoadLibrary` has no equivalent of the `dlopen` flags.
+#[cfg(not(unix))]
+pub unsafe fn open_library(
+    path: impl AsRef<Path>,
+    _flags: i32,
+) -> Result<Library, libloading::Error> {
+    Library::new(path.as_ref())
+}
+
+/// Hash the whole file and compare with the expected digest,
+/// reporting the number of bytes read so far to `progress`.
+pub fn validate_file(
+    f: &mut File,
+    algorithm: DigestAlgorithm,
+    expect_digest: Digest,
+    mut progress: impl FnMut(u64),
+) -> Result<()> {
     let mut buf = Vec::new();
-    f.read_to_end(&mut buf).unwrap();
-    let mut blake = blake::Blake::new(512).unwrap();
-    blake.update(buf.as_slice());
-    let mut digest = [0; 64];
-    blake.finalise(&mut digest);
-    if digest != expect_digest {
+    let mut chunk = vec![0; 1 << 20];
+    loop {
+        let n = f.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        progress(buf.len() as u64);
+    }
+    let digest = algorithm.digest(buf.as_slice());
+    if !bool::from(digest.ct_eq(&expect_digest)) {
         warn!("file has been tampered");
         return Err(Error::Tampered);
     }
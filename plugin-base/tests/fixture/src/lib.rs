@@ -0,0 +1,37 @@
+//! Minimal plugin loaded by the plugin-base integration tests.
+
+use plugin_base::abi_stable::std_types::RStr;
+use plugin_base::logger::LogCallback;
+use plugin_base::{declare_plugin, Plugin, PluginError};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+struct Fixture;
+
+impl Plugin for Fixture {
+    fn name(&self) -> RStr<'static> {
+        RStr::from_str("fixture")
+    }
+
+    fn on_plugin_load(&self) {
+        LOADED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Whether `on_plugin_load` ran, looked up by the test through `get_symbol`.
+#[no_mangle]
+pub extern "C" fn fixture_loaded() -> bool {
+    LOADED.load(Ordering::SeqCst)
+}
+
+/// A `sleep:<millis>` config makes the constructor block that long.
+fn create_plugin(config: Option<String>, _: LogCallback) -> Result<Fixture, PluginError> {
+    if let Some(millis) = config.as_deref().and_then(|c| c.strip_prefix("sleep:")) {
+        let millis = millis.parse().map_err(PluginError::custom)?;
+        std::thread::sleep(std::time::Duration::from_millis(millis));
+    }
+    Ok(Fixture)
+}
+
+declare_plugin!(Fixture, create_plugin);
@@ -0,0 +1,252 @@
+//! Loads a real plugin: the `fixture` crate is built, packed with a fresh key and
+//! loaded through a [`PluginManager`] verifying with that key.
+
+use ed25519_dalek::SigningKey;
+use plugin_base::{DirectorySource, Error, LoadOptions, PluginManager, PluginSource, SymbolPolicy};
+use plugin_defs::{ExportAlgorithm, Package, PackageMetadata, PluginLock};
+use rand::thread_rng;
+use semver::{Version, VersionReq};
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use std::{env, fs};
+
+/// Build the fixture plugin into the test scratch dir and return the library path.
+fn build_fixture() -> PathBuf {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fixture");
+    let status = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--package", "fixture-plugin", "--target-dir"])
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "building the fixture plugin failed");
+    target_dir
+        .join("debug")
+        .join(format!("{DLL_PREFIX}fixture_plugin{DLL_SUFFIX}"))
+}
+
+/// Pack the fixture plugin with a fresh key into `dir`, returning the package path and key.
+fn pack_fixture(dir: &Path) -> (PathBuf, SigningKey) {
+    let library = fs::read(build_fixture()).unwrap();
+    let key = SigningKey::generate(&mut thread_rng());
+    let metadata = PackageMetadata::builder()
+        .name("fixture")
+        .version(Version::new(0, 1, 0))
+        .build()
+        .unwrap();
+    let exported = Package::new(metadata, library)
+        .export_with(&key, ExportAlgorithm::None)
+        .unwrap();
+
+    let path = dir.join("fixture.cdp");
+    fs::write(&path, exported).unwrap();
+    (path, key)
+}
+
+#[test]
+fn test_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(key.verifying_key());
+    // the fixture is declared without a schema
+    let info = unsafe { plugin_mgr.validate_plugin(&path).unwrap() };
+    assert!(!info.has_config_schema);
+    unsafe { plugin_mgr.load_plugin(&path).unwrap() };
+
+    assert!(plugin_mgr.get_plugin("fixture").is_some());
+    assert!(matches!(
+        plugin_mgr.plugin_source("fixture"),
+        Some(PluginSource::File(file)) if *file == path
+    ));
+    let loaded = unsafe {
+        plugin_mgr
+            .get_symbol::<extern "C" fn() -> bool>("fixture", b"fixture_loaded")
+            .unwrap()
+    };
+    assert!(loaded(), "on_plugin_load did not run");
+}
+
+#[test]
+fn test_load_is_idempotent_by_digest() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+    let copy = dir.path().join("copy.cdp");
+    fs::copy(&path, &copy).unwrap();
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(key.verifying_key());
+    let first = unsafe { plugin_mgr.load_plugin_opts(&path, LoadOptions::default()) }.unwrap();
+    // same digest from another path: the loaded plugin is described, not loaded again
+    let again = unsafe { plugin_mgr.load_plugin_opts(&copy, LoadOptions::default()) }.unwrap();
+    assert_eq!(again.source, first.source);
+    assert_eq!(again.metadata.digest, first.metadata.digest);
+
+    let forced = LoadOptions {
+        force: true,
+        ..LoadOptions::default()
+    };
+    let replaced = unsafe { plugin_mgr.load_plugin_opts(&copy, forced) }.unwrap();
+    assert_eq!(replaced.source, PluginSource::File(copy.clone()));
+    assert_eq!(plugin_mgr.plugin_source("fixture"), Some(&replaced.source));
+
+    // every load path compares digests, not only load_plugin_opts
+    unsafe { plugin_mgr.load_plugin_bytes(&fs::read(&path).unwrap()) }.unwrap();
+    assert_eq!(plugin_mgr.plugin_source("fixture"), Some(&replaced.source));
+    assert_eq!(plugin_mgr.plugin_ids("fixture").len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_load_with_global_symbols() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(key.verifying_key())
+        .with_dlopen_flags(plugin_base::RTLD_GLOBAL | plugin_base::RTLD_NOW);
+    unsafe { plugin_mgr.load_plugin(&path).unwrap() };
+    assert!(plugin_mgr.get_plugin("fixture").is_some());
+}
+
+#[test]
+fn test_load_embedded() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+    // the fixture is built by the test and cannot be `include_bytes!`d, a leaked buffer is
+    // just as static and read-only, and the package file is gone before the load
+    let embedded: &'static [u8] = Box::leak(fs::read(&path).unwrap().into_boxed_slice());
+    fs::remove_file(&path).unwrap();
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(key.verifying_key());
+    unsafe { plugin_mgr.load_embedded(embedded) }.unwrap();
+    assert_eq!(
+        plugin_mgr.plugin_source("fixture"),
+        Some(&PluginSource::Embedded)
+    );
+    let loaded = unsafe {
+        plugin_mgr
+            .get_symbol::<extern "C" fn() -> bool>("fixture", b"fixture_loaded")
+            .unwrap()
+    };
+    assert!(loaded(), "on_plugin_load did not run");
+}
+
+#[test]
+fn test_load_plugin_by_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, key) = pack_fixture(dir.path());
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(key.verifying_key());
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_by_name("fixture", &VersionReq::STAR) },
+        Err(Error::NoPackageSource)
+    ));
+
+    let mut plugin_mgr = plugin_mgr.with_package_source(DirectorySource::new(dir.path()));
+    let too_new = VersionReq::parse(">=99").unwrap();
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin_by_name("fixture", &too_new) },
+        Err(Error::PackageNotFound { .. })
+    ));
+    unsafe { plugin_mgr.load_plugin_by_name("Fixture", &VersionReq::STAR) }.unwrap();
+    assert_eq!(
+        plugin_mgr.plugin_source("fixture"),
+        Some(&PluginSource::Bytes)
+    );
+}
+
+#[test]
+fn test_symbol_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+
+    // imported symbols such as malloc are not exports of the plugin
+    let policy = SymbolPolicy::new().require("fixture_loaded").deny("malloc");
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(key.verifying_key())
+        .with_symbol_policy(policy);
+    unsafe { plugin_mgr.load_plugin(&path).unwrap() };
+    assert!(plugin_mgr.get_plugin("fixture").is_some());
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(key.verifying_key())
+        .with_symbol_policy(SymbolPolicy::new().deny("fixture_loaded"));
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin(&path) },
+        Err(Error::DeniedSymbol { name, symbol }) if name == "fixture" && symbol == "fixture_loaded"
+    ));
+    assert!(plugin_mgr.get_plugin("fixture").is_none());
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(key.verifying_key())
+        .with_symbol_policy(SymbolPolicy::new().require("fixture_unloaded"));
+    assert!(matches!(
+        unsafe { plugin_mgr.load_plugin(&path) },
+        Err(Error::MissingSymbol(symbol)) if symbol == "fixture_unloaded"
+    ));
+}
+
+#[test]
+fn test_load_from_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+    let package = Package::import_file(&path, &key.verifying_key()).unwrap();
+    let mut lock = PluginLock::default();
+    lock.insert(&package.metadata);
+    let lock = PluginLock::verify(&lock.sign(&key).unwrap(), &key.verifying_key()).unwrap();
+
+    let mut plugin_mgr = PluginManager::new().with_verifier(key.verifying_key());
+    unsafe { plugin_mgr.load_from_lock(&lock, dir.path()).unwrap() };
+    assert_eq!(
+        plugin_mgr.plugin_source("fixture"),
+        Some(&PluginSource::File(path))
+    );
+}
+
+#[test]
+fn test_load_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let (path, key) = pack_fixture(dir.path());
+
+    let mut plugin_mgr = PluginManager::new()
+        .with_verifier(key.verifying_key())
+        .with_load_timeout(Duration::from_millis(100));
+    let hanging = LoadOptions {
+        config: Some("sleep:5000".to_string()),
+        ..LoadOptions::default()
+    };
+    let result = unsafe { plugin_mgr.load_plugin_opts(&path, hanging) };
+    assert!(matches!(result, Err(Error::LoadTimeout { name, .. }) if name == "fixture"));
+    assert!(plugin_mgr.get_plugin("fixture").is_none());
+
+    // the host regained control, a constructor within the timeout still loads
+    unsafe { plugin_mgr.load_plugin(&path).unwrap() };
+    assert!(plugin_mgr.get_plugin("fixture").is_some());
+}
+
+#[cfg(feature = "dev-unsafe")]
+#[test]
+fn test_dev_mode_loads_unsigned_library() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = dir
+        .path()
+        .join(format!("{DLL_PREFIX}fixture_plugin{DLL_SUFFIX}"));
+    fs::copy(build_fixture(), &library).unwrap();
+    fs::write(
+        dir.path().join("metadata.json"),
+        r#"{ "name": "fixture", "version": "0.1.0" }"#,
+    )
+    .unwrap();
+
+    let mut plugin_mgr = PluginManager::new();
+    assert!(unsafe { plugin_mgr.load_plugin(&library) }.is_err());
+
+    let mut plugin_mgr = PluginManager::new().dev_mode(true);
+    unsafe { plugin_mgr.load_plugin(&library).unwrap() };
+    assert_eq!(
+        plugin_mgr.plugin_source("fixture"),
+        Some(&PluginSource::Unsigned(library))
+    );
+}
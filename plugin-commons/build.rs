@@ -11,6 +11,7 @@ fn main() {
         .unwrap();
     let bincode_version = find_version(&metadata, "bincode");
     let abi_stable_version = find_version(&metadata, "abi_stable");
+    let target = std::env::var("TARGET").unwrap();
 
     // write bincode version to file
     let out_dir = std::env::var("OUT_DIR").unwrap();
@@ -20,6 +21,7 @@ fn main() {
         format!(
             r#"pub const BINCODE_VERSION: &str = "{bincode_version}";
 pub const ABI_STABLE_VERSION: &str = "{abi_stable_version}";
+pub const TARGET: &str = "{target}";
 "#
         ),
     )
@@ -0,0 +1,28 @@
+//! Compares [`DigestAlgorithm::Blake512`] with [`DigestAlgorithm::Blake512Tree`] on a
+//! 100 MB library, run with and without `--features parallel-digest`:
+//!
+//! ```sh
+//! cargo bench -p plugin-defs --bench digest
+//! cargo bench -p plugin-defs --bench digest --features parallel-digest
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use plugin_defs::DigestAlgorithm;
+
+const LIBRARY_SIZE: usize = 100 << 20;
+
+fn digest(c: &mut Criterion) {
+    let library: Vec<u8> = (0..LIBRARY_SIZE).map(|i| (i * 31 % 251) as u8).collect();
+    let mut group = c.benchmark_group("digest");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(LIBRARY_SIZE as u64));
+    for algorithm in [DigestAlgorithm::Blake512, DigestAlgorithm::Blake512Tree] {
+        group.bench_function(format!("{algorithm:?}"), |b| {
+            b.iter(|| algorithm.digest(&library))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, digest);
+criterion_main!(benches);
@@ -0,0 +1,195 @@
+use crate::{DependencySpec, DigestAlgorithm, Error, PackageMetadata, Result};
+use semver::{Version, VersionReq};
+
+/// Builder for [`PackageMetadata`].
+///
+/// The digest is left empty, it is filled in by [`Package::new`](crate::Package::new).
+#[derive(Clone, Debug, Default)]
+pub struct PackageMetadataBuilder {
+    name: Option<String>,
+    version: Option<Version>,
+    plugin_base_version: Option<Version>,
+    digest_algorithm: DigestAlgorithm,
+    dependencies: Vec<DependencySpec>,
+    target: Option<String>,
+    description: Option<String>,
+    changelog: Option<String>,
+    priority: i32,
+    id: Option<String>,
+}
+
+impl PackageMetadata {
+    pub fn builder() -> PackageMetadataBuilder {
+        PackageMetadataBuilder::default()
+    }
+}
+
+impl PackageMetadataBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn plugin_base_version(mut self, version: Version) -> Self {
+        self.plugin_base_version = Some(version);
+        self
+    }
+
+    pub fn digest_algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.digest_algorithm = algorithm;
+        self
+    }
+
+    pub fn dependency(mut self, name: impl Into<String>, version: VersionReq) -> Self {
+        self.dependencies.push(DependencySpec {
+            name: name.into(),
+            version,
+            id: None,
+        });
+        self
+    }
+
+    /// Depend on the plugin with the given [`PackageMetadata::id`], see [`DependencySpec::id`].
+    pub fn dependency_with_id(
+        mut self,
+        name: impl Into<String>,
+        id: impl Into<String>,
+        version: VersionReq,
+    ) -> Self {
+        self.dependencies.push(DependencySpec {
+            name: name.into(),
+            version,
+            id: Some(id.into()),
+        });
+        self
+    }
+
+    pub fn target(mut self, triple: impl Into<String>) -> Self {
+        self.target = Some(triple.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn changelog(mut self, changelog: impl Into<String>) -> Self {
+        self.changelog = Some(changelog.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn build(self) -> Result<PackageMetadata> {
+        let name = self.name.ok_or(Error::MissingField("name"))?;
+        let version = self.version.ok_or(Error::MissingField("version"))?;
+        let metadata = PackageMetadata {
+            name,
+            digest: String::new(),
+            digest_algorithm: self.digest_algorithm,
+            version,
+            plugin_base_version: self.plugin_base_version,
+            dependencies: self.dependencies,
+            target: self.target,
+            description: self.description,
+            changelog: self.changelog,
+            priority: self.priority,
+            id: self.id,
+            metadata_digest: None,
+        };
+        metadata.validate()?;
+        Ok(metadata)
+    }
+}
+
+#[test]
+fn test_builder_rejects_duplicate_dependency() {
+    let result = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .dependency("web", VersionReq::parse("^1").unwrap())
+        .dependency("web", VersionReq::parse("^2").unwrap())
+        .build();
+    assert!(matches!(result, Err(Error::DuplicateDependency(name)) if name == "web"));
+}
+
+#[test]
+fn test_builder_rejects_self_dependency() {
+    let result = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .dependency("spider", VersionReq::STAR)
+        .build();
+    assert!(matches!(result, Err(Error::SelfDependency(_))));
+}
+
+#[test]
+fn test_dependency_with_id() {
+    // another vendor's spider is not a self dependency
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .id("org.example.spider")
+        .version(Version::new(1, 0, 0))
+        .dependency_with_id("spider", "org.other.spider", VersionReq::STAR)
+        .build()
+        .unwrap();
+    assert_eq!(
+        metadata.dependencies[0].id.as_deref(),
+        Some("org.other.spider")
+    );
+
+    let result = PackageMetadata::builder()
+        .name("spider")
+        .id("org.example.spider")
+        .version(Version::new(1, 0, 0))
+        .dependency_with_id("spider", "org.example.spider", VersionReq::STAR)
+        .build();
+    assert!(matches!(result, Err(Error::SelfDependency(_))));
+}
+
+#[test]
+fn test_validate_strict() {
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(0, 0, 0))
+        .dependency("storage", VersionReq::STAR)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        metadata.validate_strict(),
+        Err(Error::UnreleasedVersion)
+    ));
+
+    let metadata = PackageMetadata {
+        version: Version::new(0, 1, 0),
+        ..metadata
+    };
+    assert!(matches!(
+        metadata.validate_strict(),
+        Err(Error::WildcardDependency(name)) if name == "storage"
+    ));
+
+    let metadata = PackageMetadata {
+        dependencies: vec![DependencySpec {
+            name: "storage".to_string(),
+            version: VersionReq::parse("^1.2").unwrap(),
+            id: None,
+        }],
+        ..metadata
+    };
+    metadata.validate_strict().unwrap();
+}
@@ -0,0 +1,34 @@
+/// Magic number starting a trained zstd dictionary, followed by its id.
+const ZSTD_DICT_MAGIC: u32 = 0xEC30A437;
+
+/// A zstd dictionary shared by the packager and the host, see [`ExportAlgorithm::ZstdDict`](crate::ExportAlgorithm::ZstdDict).
+///
+/// Training one dictionary on a set of similar plugins, e.g. with `zstd --train`, makes
+/// small packages compress much better than on their own.
+#[derive(Clone, Debug)]
+pub struct Dictionary {
+    id: u32,
+    content: Vec<u8>,
+}
+
+impl Dictionary {
+    /// Trained dictionaries are identified by the id in their header,
+    /// raw content dictionaries by the CRC32 of their content.
+    pub fn new(content: Vec<u8>) -> Self {
+        let id = match content.get(..8) {
+            Some(header) if header[..4] == ZSTD_DICT_MAGIC.to_le_bytes() => {
+                u32::from_le_bytes(header[4..].try_into().unwrap())
+            }
+            _ => crc32fast::hash(&content),
+        };
+        Dictionary { id, content }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+/// Native library formats recognized by their magic bytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LibraryFormat {
+    Elf,
+    MachO,
+    Pe,
+}
+
+impl LibraryFormat {
+    /// Format of the libraries the platform this was built for loads.
+    pub fn native() -> Option<Self> {
+        if cfg!(target_os = "windows") {
+            Some(LibraryFormat::Pe)
+        } else if cfg!(target_vendor = "apple") {
+            Some(LibraryFormat::MachO)
+        } else if cfg!(unix) {
+            Some(LibraryFormat::Elf)
+        } else {
+            None
+        }
+    }
+
+    /// Format of the libraries built for a target triple, e.g. `x86_64-pc-windows-msvc`.
+    pub fn for_target(target: &str) -> Self {
+        if target.contains("-windows") {
+            LibraryFormat::Pe
+        } else if target.contains("-apple-") {
+            LibraryFormat::MachO
+        } else {
+            LibraryFormat::Elf
+        }
+    }
+
+    /// Recognize a library by its first bytes.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        let magic = bytes.get(..4)?;
+        match magic {
+            [0x7f, b'E', b'L', b'F'] => Some(LibraryFormat::Elf),
+            // 32 and 64 bit, either byte order, and universal binaries
+            [0xfe, 0xed, 0xfa, 0xce | 0xcf]
+            | [0xce | 0xcf, 0xfa, 0xed, 0xfe]
+            | [0xca, 0xfe, 0xba, 0xbe] => Some(LibraryFormat::MachO),
+            [b'M', b'Z', ..] => Some(LibraryFormat::Pe),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LibraryFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LibraryFormat::Elf => "ELF",
+            LibraryFormat::MachO => "Mach-O",
+            LibraryFormat::Pe => "PE",
+        })
+    }
+}
+
+#[test]
+fn test_format_for_target() {
+    assert_eq!(
+        LibraryFormat::for_target("x86_64-pc-windows-msvc"),
+        LibraryFormat::Pe
+    );
+    assert_eq!(
+        LibraryFormat::for_target("aarch64-apple-darwin"),
+        LibraryFormat::MachO
+    );
+    assert_eq!(
+        LibraryFormat::for_target("x86_64-unknown-linux-gnu"),
+        LibraryFormat::Elf
+    );
+}
@@ -1,15 +1,34 @@
 #[macro_use]
 extern crate log;
 
+use bincode::Options;
 use ed25519::Signature;
 use rand::distributions::{Alphanumeric, DistString};
 use rand::thread_rng;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
 use tempfile::TempDir;
 
+mod builder;
+mod dict;
+mod digest;
+mod format;
+mod lock;
+mod scheme;
+
+pub use builder::PackageMetadataBuilder;
+pub use dict::Dictionary;
+pub use digest::{fingerprint, Digest, DigestAlgorithm, TREE_CHUNK_SIZE};
+pub use format::LibraryFormat;
+pub use lock::{LockedPlugin, PluginLock};
+pub use scheme::{Ed25519Scheme, SchemeImpl, SignatureScheme};
+
 #[cfg(windows)]
 const PLUGIN_EXT: &str = "dll";
 #[cfg(all(unix, not(target_os = "macos")))]
@@ -25,126 +44,985 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("signature error: {0}")]
     Signature(#[from] ed25519::Error),
+    #[error("package envelope is corrupt")]
+    CorruptEnvelope,
+    #[error("not a plugin package")]
+    BadMagic,
+    #[error("unsupported package format version {0}")]
+    UnsupportedFormatVersion(u16),
+    #[error("package file is truncated")]
+    TruncatedPackage,
     #[error("package digest mismatch")]
     InvalidDigest,
     #[error("unable to read the package file: {0}")]
     UnableToReadPackage(std::io::Error),
+    #[error("export algorithm {0:?} is not compiled in")]
+    UnsupportedAlgorithm(ExportAlgorithm),
+    #[error("package needs zstd dictionary {0:#010x}")]
+    MissingDictionary(u32),
+    #[error("invalid resource name {0:?}")]
+    InvalidResourceName(String),
+    #[error("invalid plugin lock: {0}")]
+    InvalidLock(String),
+    #[error("missing metadata field: {0}")]
+    MissingField(&'static str),
+    #[error("package name is empty")]
+    EmptyName,
+    #[error("duplicate dependency: {0}")]
+    DuplicateDependency(String),
+    #[error("dependency name is empty")]
+    EmptyDependencyName,
+    #[error("package {0} depends on itself")]
+    SelfDependency(String),
+    #[error("package id is empty")]
+    EmptyId,
+    #[error("package version 0.0.0 is not a release")]
+    UnreleasedVersion,
+    #[error("dependency {0} accepts any version")]
+    WildcardDependency(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ExportAlgorithm {
     None,
     Zstd,
+    /// zstd with the [`Dictionary`] of the given id
+    ZstdDict(u32),
+}
+
+impl ExportAlgorithm {
+    pub fn encode(self, data: &[u8]) -> Result<Vec<u8>> {
+        self.encode_with(data, None)
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decode_with(data, &[])
+    }
+
+    /// Like [`ExportAlgorithm::encode`], `dict` must be given for [`ExportAlgorithm::ZstdDict`].
+    pub fn encode_with(self, data: &[u8], dict: Option<&Dictionary>) -> Result<Vec<u8>> {
+        match self {
+            ExportAlgorithm::None => Ok(data.to_vec()),
+            #[cfg(feature = "zstd")]
+            ExportAlgorithm::Zstd => Ok(zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+            #[cfg(feature = "zstd")]
+            ExportAlgorithm::ZstdDict(id) => {
+                let dict = dict
+                    .filter(|dict| dict.id() == id)
+                    .ok_or(Error::MissingDictionary(id))?;
+                let mut encoder = zstd::Encoder::with_dictionary(
+                    Vec::new(),
+                    zstd::DEFAULT_COMPRESSION_LEVEL,
+                    dict.content(),
+                )?;
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(not(feature = "zstd"))]
+            ExportAlgorithm::Zstd | ExportAlgorithm::ZstdDict(_) => {
+                let _ = dict;
+                Err(Error::UnsupportedAlgorithm(self))
+            }
+        }
+    }
+
+    /// Like [`ExportAlgorithm::decode`], picking the dictionary by id among `dictionaries`.
+    pub fn decode_with(self, data: &[u8], dictionaries: &[Dictionary]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        self.decoder(data, dictionaries)?
+            .read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    /// Upper bound of the length `data` decodes to, read from the zstd frame headers alone.
+    ///
+    /// Bounds what a crafted length prefix in an unverified payload can make the decoder
+    /// allocate.
+    pub fn decoded_len_bound(self, data: &[u8]) -> Result<u64> {
+        match self {
+            ExportAlgorithm::None => Ok(data.len() as u64),
+            #[cfg(feature = "zstd")]
+            ExportAlgorithm::Zstd | ExportAlgorithm::ZstdDict(_) => zstd_decoded_len_bound(data)
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt zstd frame")
+                        .into()
+                }),
+            #[cfg(not(feature = "zstd"))]
+            ExportAlgorithm::Zstd | ExportAlgorithm::ZstdDict(_) => {
+                Err(Error::UnsupportedAlgorithm(self))
+            }
+        }
+    }
+
+    /// Decode `data` as it is read, without holding the whole decoded payload.
+    pub fn decoder<'a>(
+        self,
+        data: &'a [u8],
+        dictionaries: &'a [Dictionary],
+    ) -> Result<Box<dyn Read + 'a>> {
+        match self {
+            ExportAlgorithm::None => Ok(Box::new(data)),
+            #[cfg(feature = "zstd")]
+            ExportAlgorithm::Zstd => Ok(Box::new(zstd::Decoder::with_buffer(data)?)),
+            #[cfg(feature = "zstd")]
+            ExportAlgorithm::ZstdDict(id) => {
+                let dict = dictionaries
+                    .iter()
+                    .find(|dict| dict.id() == id)
+                    .ok_or(Error::MissingDictionary(id))?;
+                Ok(Box::new(zstd::Decoder::with_dictionary(
+                    data,
+                    dict.content(),
+                )?))
+            }
+            #[cfg(not(feature = "zstd"))]
+            ExportAlgorithm::Zstd | ExportAlgorithm::ZstdDict(_) => {
+                let _ = dictionaries;
+                Err(Error::UnsupportedAlgorithm(self))
+            }
+        }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Largest block a zstd frame decodes to.
+#[cfg(feature = "zstd")]
+const ZSTD_BLOCK_MAX: u64 = 128 * 1024;
+
+/// Sum of what the blocks of the zstd frames in `data` decode to at most, walking the
+/// frame and block headers without decoding anything. `None` if a header is malformed.
+#[cfg(feature = "zstd")]
+fn zstd_decoded_len_bound(mut data: &[u8]) -> Option<u64> {
+    let mut bound = 0u64;
+    while !data.is_empty() {
+        let (magic, rest) = data.split_first_chunk::<4>()?;
+        let magic = u32::from_le_bytes(*magic);
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            // skippable frame
+            let (len, rest) = rest.split_first_chunk::<4>()?;
+            data = rest.get(u32::from_le_bytes(*len) as usize..)?;
+            continue;
+        }
+        if magic != 0xfd2f_b528 {
+            return None;
+        }
+        let (&descriptor, rest) = rest.split_first()?;
+        let single_segment = descriptor & 0x20 != 0;
+        let window_len = usize::from(!single_segment);
+        let dict_id_len = [0, 1, 2, 4][usize::from(descriptor & 0x03)];
+        let content_size_len = match descriptor >> 6 {
+            0 => usize::from(single_segment),
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let mut rest = rest.get(window_len + dict_id_len + content_size_len..)?;
+        loop {
+            let (header, blocks) = rest.split_first_chunk::<3>()?;
+            let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+            let size = (header >> 3) as usize;
+            let (len, decoded) = match (header >> 1) & 0x03 {
+                // raw
+                0 => (size, size as u64),
+                // a single byte repeated
+                1 => (1, size as u64),
+                // compressed
+                2 => (size, ZSTD_BLOCK_MAX),
+                _ => return None,
+            };
+            rest = blocks.get(len..)?;
+            bound = bound.checked_add(decoded.min(ZSTD_BLOCK_MAX))?;
+            if header & 0x01 != 0 {
+                break;
+            }
+        }
+        let checksum_len = if descriptor & 0x04 != 0 { 4 } else { 0 };
+        data = rest.get(checksum_len..)?;
+    }
+    Some(bound)
+}
+
+/// The bincode configuration of every package envelope and payload.
+///
+/// Fixed-width little-endian integers, the same bytes `bincode::serialize`
+/// has always produced, spelled out so a bincode upgrade or a big-endian
+/// host can't change the wire format.
+pub fn bincode_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .allow_trailing_bytes()
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct PackageExport {
     pub alog: ExportAlgorithm,
     pub payload: Vec<u8>,
     pub signature: Signature,
+    pub scheme: SignatureScheme,
+    /// format version the package was read with, which decides the layout of the payload,
+    /// [`FORMAT_VERSION`] for new exports
+    #[serde(skip)]
+    pub format_version: u16,
+}
+
+/// Length of the CRC32 trailing a serialized [`PackageExport`].
+const CHECKSUM_LEN: usize = 4;
+
+/// Bytes every serialized package starts with, followed by the little-endian
+/// [`FORMAT_VERSION`].
+pub const PACKAGE_MAGIC: &[u8; 4] = b"CDP\0";
+
+/// Version of the serialized package format written by [`PackageExport::write_to`].
+///
+/// Version 2 writes every field of the envelope and of the payload. Version 1, and packages
+/// without the header, leave out an ed25519 signature scheme and empty resources, so those
+/// are told apart by where the input ends.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Last format version whose trailing fields may be left out, packages without the header
+/// are read as version 0.
+const LEGACY_FORMAT_VERSION: u16 = 1;
+
+/// Feeds everything written through it to a CRC32.
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    count: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+}
+
+impl PackageExport {
+    /// Parse a serialized package.
+    ///
+    /// The package starts with [`PACKAGE_MAGIC`] and the format version, versions newer than
+    /// [`FORMAT_VERSION`] are refused with [`Error::UnsupportedFormatVersion`]. Packages
+    /// written before the header existed are still read, input that is neither is reported
+    /// as [`Error::BadMagic`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some(rest) = bytes.strip_prefix(PACKAGE_MAGIC) else {
+            return Self::from_headerless(bytes).map_err(|e| match e {
+                Error::CorruptEnvelope => Error::BadMagic,
+                e => e,
+            });
+        };
+        let (version, envelope) = rest
+            .split_first_chunk::<2>()
+            .ok_or(Error::TruncatedPackage)?;
+        let version = u16::from_le_bytes(*version);
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(Error::UnsupportedFormatVersion(version));
+        }
+        Self::from_envelope(envelope, version)
+    }
+
+    /// Parse the envelope following the header.
+    ///
+    /// The envelope is followed by a CRC32 of its bytes, checked before anything is decoded.
+    /// A mismatch is reported as [`Error::TruncatedPackage`] if the input ends too early,
+    /// [`Error::CorruptEnvelope`] otherwise.
+    fn from_envelope(bytes: &[u8], format_version: u16) -> Result<Self> {
+        if let Some(envelope) = Self::checked_envelope(bytes) {
+            return Ok(Self::parse(envelope, format_version)?.0);
+        }
+        // only tells a truncated envelope from a corrupt one, never accepted unchecked
+        match Self::parse(bytes, format_version) {
+            Err(Error::TruncatedPackage) => Err(Error::TruncatedPackage),
+            _ => Err(Error::CorruptEnvelope),
+        }
+    }
+
+    /// Parse a package written before the header existed.
+    ///
+    /// Packages written before the checksum was added too are accepted only if they parse
+    /// exactly, anything else is reported as [`Error::CorruptEnvelope`], or
+    /// [`Error::TruncatedPackage`] if the input ends too early.
+    fn from_headerless(bytes: &[u8]) -> Result<Self> {
+        if let Some(envelope) = Self::checked_envelope(bytes) {
+            return Ok(Self::parse(envelope, 0)?.0);
+        }
+        let (export, len) = Self::parse(bytes, 0).map_err(|e| match e {
+            Error::Bincode(_) => Error::CorruptEnvelope,
+            e => e,
+        })?;
+        if len != bytes.len() {
+            return Err(Error::CorruptEnvelope);
+        }
+        Ok(export)
+    }
+
+    /// The envelope without its CRC32, `None` if the checksum does not match.
+    fn checked_envelope(bytes: &[u8]) -> Option<&[u8]> {
+        let (envelope, checksum) = bytes.split_last_chunk::<CHECKSUM_LEN>()?;
+        (crc32fast::hash(envelope).to_le_bytes() == *checksum).then_some(envelope)
+    }
+
+    /// Parse an envelope of `format_version`, returning it with the number of bytes it took.
+    fn parse(envelope: &[u8], format_version: u16) -> Result<(Self, usize)> {
+        let truncated = |e: bincode::Error| match *e {
+            bincode::ErrorKind::Io(ref io) if io.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Error::TruncatedPackage
+            }
+            _ => Error::Bincode(e),
+        };
+        if format_version > LEGACY_FORMAT_VERSION {
+            let mut export: Self = bincode_options().deserialize(envelope).map_err(truncated)?;
+            export.format_version = format_version;
+            let len = bincode_options().serialized_size(&export)? as usize;
+            return Ok((export, len));
+        }
+        let (alog, payload, signature): (ExportAlgorithm, Vec<u8>, Signature) =
+            bincode_options().deserialize(envelope).map_err(truncated)?;
+        let mut len = bincode_options().serialized_size(&(alog, &payload, &signature))? as usize;
+        // ed25519 envelopes end after the signature
+        let scheme = match &envelope[len..] {
+            [] => SignatureScheme::Ed25519,
+            rest => {
+                let scheme = bincode_options().deserialize(rest).map_err(truncated)?;
+                len += bincode_options().serialized_size(&scheme)? as usize;
+                scheme
+            }
+        };
+        let export = Self {
+            alog,
+            payload,
+            signature,
+            scheme,
+            format_version,
+        };
+        Ok((export, len))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Write the header and the envelope followed by its CRC32.
+    ///
+    /// The header records the format version of the payload, a package read without the
+    /// header is written as version 1.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let format_version = self.format_version.max(LEGACY_FORMAT_VERSION);
+        writer.write_all(PACKAGE_MAGIC)?;
+        writer.write_all(&format_version.to_le_bytes())?;
+        let mut writer = ChecksumWriter {
+            inner: writer,
+            hasher: crc32fast::Hasher::new(),
+        };
+        bincode_options().serialize_into(&mut writer, self)?;
+        let checksum = writer.hasher.finalize();
+        writer.inner.write_all(&checksum.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn compressed_payload_len(&self) -> usize {
+        self.payload.len()
+    }
+
+    /// The signed payload, decompressed according to `alog`.
+    pub fn decode_payload(&self) -> Result<Vec<u8>> {
+        self.decode_payload_with(&[])
+    }
+
+    /// Like [`PackageExport::decode_payload`], with the dictionaries the payload may need.
+    pub fn decode_payload_with(&self, dictionaries: &[Dictionary]) -> Result<Vec<u8>> {
+        self.alog.decode_with(self.payload.as_slice(), dictionaries)
+    }
+
+    /// Deserialize the package from `reader`, the decoded payload.
+    ///
+    /// No more than [`ExportAlgorithm::decoded_len_bound`] bytes are read, so a length
+    /// prefix larger than the payload fails instead of being allocated.
+    fn read_package<R: Read>(&self, reader: R) -> Result<Package> {
+        let limit = self.alog.decoded_len_bound(&self.payload)?;
+        if self.format_version <= LEGACY_FORMAT_VERSION {
+            return Ok(de::read_legacy_package(reader, limit)?);
+        }
+        Ok(bincode_options()
+            .with_limit(limit)
+            .deserialize_from(reader)?)
+    }
+
+    /// Deserialize the package while the payload is decompressed, so the decoded payload
+    /// and the package are never both in memory. Returns the decoded payload length too.
+    ///
+    /// The signature is *not* verified. This is the only path that streams, checking the
+    /// signature needs the decoded payload in one piece, see
+    /// [`PackageExport::decode_package_verified`].
+    fn decode_package_unverified(&self) -> Result<(Package, usize)> {
+        let mut reader = CountingReader {
+            inner: self.alog.decoder(self.payload.as_slice(), &[])?,
+            count: 0,
+        };
+        let package = self.read_package(&mut reader)?;
+        Ok((package, reader.count))
+    }
+
+    /// Decode the payload, check the signature over it, then deserialize the package.
+    ///
+    /// Unlike the unverified paths this does not stream: ed25519 signs the whole payload,
+    /// so the decoded payload is kept in one piece until the signature is checked, and the
+    /// library is hashed in a separate pass by [`Package::digest_check`].
+    fn decode_package_verified<V>(
+        &self,
+        verifier: &V,
+        dictionaries: &[Dictionary],
+    ) -> Result<Package>
+    where
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
+    {
+        let payload = self.decode_payload_with(dictionaries)?;
+        self.scheme.verify(verifier, &payload, &self.signature)?;
+        self.read_package(payload.as_slice())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Package {
     pub metadata: PackageMetadata,
-    pub library: Vec<u8>,
+    library: Vec<u8>,
+    /// digest of `library` and the algorithm it was computed with, reset by
+    /// [`Package::library_mut`]
+    library_digest: OnceLock<(DigestAlgorithm, Digest)>,
+    /// data files shipped with the library, keyed by their path relative to it
+    pub resources: BTreeMap<String, Vec<u8>>,
+    /// bincode version recorded in the package it was read from, `None` for new packages.
+    /// Serializing always records the versions plugin-defs was built with.
+    pub embedded_bincode_version: Option<String>,
+    /// abi_stable version recorded in the package it was read from, `None` for new packages
+    pub embedded_abi_stable_version: Option<String>,
+}
+
+/// Sizes of a serialized package, in bytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PackageSizes {
+    /// the whole serialized package
+    pub package: usize,
+    /// the payload as stored, i.e. after compression
+    pub compressed_payload: usize,
+    /// the payload after decompression
+    pub payload: usize,
+    /// the library released to disk on load
+    pub library: usize,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A package together with the signature and algorithm it was imported with.
+#[derive(Clone, Debug)]
+pub struct VerifiedPackage {
+    pub package: Package,
+    pub signature: Signature,
+    pub algorithm: ExportAlgorithm,
+    pub scheme: SignatureScheme,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PackageMetadata {
     pub name: String,
     #[serde(default)]
     pub digest: String,
+    #[serde(default)]
+    pub digest_algorithm: DigestAlgorithm,
     pub version: Version,
+    /// version of plugin-base the library was compiled against, recorded at pack time
+    #[serde(default)]
+    pub plugin_base_version: Option<Version>,
     #[serde(default)]
     pub dependencies: Vec<DependencySpec>,
+    /// target triple the library was built for, checked at load if present
+    #[serde(default)]
+    pub target: Option<String>,
+    /// what the plugin does, for operators, ignored by the loader
+    #[serde(default)]
+    pub description: Option<String>,
+    /// changes in this version, for operators, ignored by the loader
+    #[serde(default)]
+    pub changelog: Option<String>,
+    /// plugins with a higher priority are dispatched to first, see `PluginManager::broadcast`.
+    /// Left out of the JSON when 0, so older metadata keeps its metadata digest.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// vendor-chosen stable identifier, e.g. a UUID, telling apart plugins that share a
+    /// name, see `PluginId`. Left out of the JSON when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// hex digest of the rest of the metadata, see [`PackageMetadata::metadata_digest`]
+    #[serde(default)]
+    pub metadata_digest: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DependencySpec {
     pub name: String,
     pub version: VersionReq,
+    /// [`PackageMetadata::id`] of the plugin depended on, only that plugin satisfies the
+    /// dependency then. Without it any plugin with the name does. Left out of the JSON
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+fn is_zero(priority: &i32) -> bool {
+    *priority == 0
+}
+
+impl PackageMetadata {
+    /// Check that the name and id are non-empty and the dependencies are well-formed:
+    /// non-empty, unique, and not referring to this package itself.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(Error::EmptyName);
+        }
+        if self.id.as_deref().is_some_and(|id| id.trim().is_empty()) {
+            return Err(Error::EmptyId);
+        }
+        let mut seen = HashSet::new();
+        for dep in self.dependencies.iter() {
+            if dep.name.is_empty() {
+                return Err(Error::EmptyDependencyName);
+            }
+            if dep.id.as_deref().is_some_and(|id| id.trim().is_empty()) {
+                return Err(Error::EmptyId);
+            }
+            // a plugin of the same name by another vendor is not this package
+            let itself = match &dep.id {
+                Some(id) => self.id.as_ref() == Some(id),
+                None => dep.name == self.name,
+            };
+            if itself {
+                return Err(Error::SelfDependency(dep.name.clone()));
+            }
+            if !seen.insert((dep.name.as_str(), dep.id.as_deref())) {
+                return Err(Error::DuplicateDependency(dep.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// [`PackageMetadata::validate`], and also reject a `0.0.0` version and dependencies
+    /// on any version (`*`), which are rarely meant to be published.
+    pub fn validate_strict(&self) -> Result<()> {
+        self.validate()?;
+        if self.version == Version::new(0, 0, 0) {
+            return Err(Error::UnreleasedVersion);
+        }
+        if let Some(dep) = self
+            .dependencies
+            .iter()
+            .find(|dep| dep.version == VersionReq::STAR)
+        {
+            return Err(Error::WildcardDependency(dep.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Where the metadata of an unpacked library is looked for, in order:
+    /// `<libname>.metadata.json`, then `metadata.json`, next to the library.
+    pub fn sidecar_paths(library: &Path) -> Vec<PathBuf> {
+        let dir = library.parent().unwrap_or_else(|| Path::new("."));
+        let mut candidates = Vec::new();
+        if let Some(stem) = library.file_stem() {
+            candidates.push(dir.join(format!("{}.metadata.json", stem.to_string_lossy())));
+        }
+        candidates.push(dir.join("metadata.json"));
+        candidates
+    }
+
+    /// Digest of the metadata itself, using its `digest_algorithm`.
+    ///
+    /// Computed over the JSON of the metadata with sorted keys, leaving out `digest` and
+    /// `metadata_digest`, so a metadata-only record can be checked without the library
+    /// or the signing key.
+    pub fn metadata_digest(&self) -> Digest {
+        let mut canonical = self.clone();
+        canonical.digest = String::new();
+        canonical.metadata_digest = None;
+        // `Value` objects keep their keys sorted
+        let value = serde_json::to_value(&canonical).expect("metadata is valid json");
+        let json = serde_json::to_vec(&value).expect("metadata is valid json");
+        self.digest_algorithm.digest(&json)
+    }
+
+    /// Whether the recorded `metadata_digest` matches the metadata.
+    /// Metadata packed before metadata digests were recorded has none and never matches.
+    pub fn metadata_digest_check(&self) -> bool {
+        self.metadata_digest
+            .as_deref()
+            .and_then(|recorded| hex::decode(recorded).ok())
+            .is_some_and(|recorded| Digest::from(recorded).ct_eq(&self.metadata_digest()).into())
+    }
 }
 
 impl Package {
     pub fn new(metadata: PackageMetadata, library: Vec<u8>) -> Self {
-        let mut this = Self { metadata, library };
+        Self::with_resources(metadata, library, BTreeMap::new())
+    }
 
-        this.metadata.digest = hex::encode(this.digest());
+    /// Assemble a package from metadata whose digests are trusted as they are, without
+    /// hashing the library. Use [`Package::digest_check`] to verify them.
+    pub fn from_parts(metadata: PackageMetadata, library: Vec<u8>) -> Self {
+        Self {
+            metadata,
+            library,
+            library_digest: OnceLock::new(),
+            resources: BTreeMap::new(),
+            embedded_bincode_version: None,
+            embedded_abi_stable_version: None,
+        }
+    }
+
+    /// Like [`Package::new`], shipping data files alongside the library.
+    pub fn with_resources(
+        metadata: PackageMetadata,
+        library: Vec<u8>,
+        resources: BTreeMap<String, Vec<u8>>,
+    ) -> Self {
+        let mut this = Self {
+            metadata,
+            library,
+            library_digest: OnceLock::new(),
+            resources,
+            embedded_bincode_version: None,
+            embedded_abi_stable_version: None,
+        };
+
+        this.metadata.digest = this.digest().to_string();
+        this.metadata.metadata_digest = Some(this.metadata_digest().to_string());
         this
     }
 
-    pub fn digest(&self) -> [u8; 64] {
-        let mut blake = blake::Blake::new(512).unwrap();
-        blake.update(self.library.as_slice());
-        let mut digest = [0; 64];
-        blake.finalise(&mut digest);
-        digest
+    /// Digest covering the library and the resources.
+    ///
+    /// Without resources this is the [`Package::library_digest`], otherwise the library
+    /// digest is hashed together with each resource name and digest, in name order.
+    pub fn digest(&self) -> Digest {
+        let library_digest = self.library_digest();
+        if self.resources.is_empty() {
+            return library_digest;
+        }
+        let algorithm = self.metadata.digest_algorithm;
+        let mut data = library_digest.as_bytes().to_vec();
+        for (name, content) in self.resources.iter() {
+            data.extend_from_slice(&(name.len() as u64).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(algorithm.digest(content).as_bytes());
+        }
+        algorithm.digest(&data)
+    }
+
+    pub fn library(&self) -> &[u8] {
+        &self.library
     }
 
-    pub fn digest_check(&self) -> bool {
-        let provided_digest: Option<[u8; 64]> = hex::decode(&self.metadata.digest)
+    /// Mutable access to the library, forgetting its cached digest.
+    pub fn library_mut(&mut self) -> &mut Vec<u8> {
+        self.library_digest.take();
+        &mut self.library
+    }
+
+    /// Digest of the library alone, what a released library file hashes to.
+    ///
+    /// Hashed once and cached, as long as the digest algorithm in the metadata stays the same.
+    pub fn library_digest(&self) -> Digest {
+        let algorithm = self.metadata.digest_algorithm;
+        match self.library_digest.get() {
+            Some((cached, digest)) if *cached == algorithm => digest.clone(),
+            _ => {
+                let digest = algorithm.digest(self.library.as_slice());
+                let _ = self.library_digest.set((algorithm, digest.clone()));
+                digest
+            }
+        }
+    }
+
+    /// The digest recorded in the metadata, if it is well-formed for the declared algorithm.
+    pub fn recorded_digest(&self) -> Option<Digest> {
+        hex::decode(&self.metadata.digest)
             .ok()
-            .and_then(|d| d.try_into().ok());
-        if let Some(provided_digest) = provided_digest {
-            if provided_digest == self.digest() {
+            .filter(|d| d.len() == self.metadata.digest_algorithm.len())
+            .map(Digest::from)
+    }
+
+    /// See [`PackageMetadata::metadata_digest`].
+    pub fn metadata_digest(&self) -> Digest {
+        self.metadata.metadata_digest()
+    }
+
+    pub fn digest_check(&self) -> bool {
+        if let Some(provided_digest) = self.recorded_digest() {
+            if provided_digest.ct_eq(&self.digest()).into() {
                 return true;
             }
         }
         false
     }
 
-    pub fn export<S>(&self, signer: S) -> Result<Vec<u8>>
+    /// Sign and serialize the package.
+    ///
+    /// The output is deterministic: bincode encoding, zstd at a fixed level and ed25519
+    /// signatures all depend only on their inputs, and no timestamp is embedded. Packing the
+    /// same library and metadata with the same key yields byte-identical packages.
+    pub fn export<S>(&self, signer: &S) -> Result<Vec<u8>>
     where
-        S: ed25519::signature::Signer<Signature>,
+        S: ed25519::signature::Signer<Signature> + ?Sized,
     {
-        let result = bincode::serialize(&self)?;
-        let signature = signer.try_sign(&result)?;
+        self.sign(signer)?.to_bytes()
+    }
 
-        let compressed = zstd::encode_all(result.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    /// Like [`Package::export`], but compressing the payload with `alog`.
+    ///
+    /// [`ExportAlgorithm::None`] skips compression. A payload that compression would not
+    /// shrink is stored with [`ExportAlgorithm::None`] whatever `alog` is.
+    pub fn export_with<S>(&self, signer: &S, alog: ExportAlgorithm) -> Result<Vec<u8>>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        self.sign_with(signer, alog)?.to_bytes()
+    }
 
-        let exported = PackageExport {
-            alog: ExportAlgorithm::Zstd,
-            payload: compressed,
-            signature,
+    /// Like [`Package::export`], but streams the envelope into `writer`.
+    ///
+    /// ed25519 signs the whole message, so the serialized package is still held once
+    /// to be signed and compressed, but the final envelope is never built in memory.
+    pub fn export_to_writer<S, W>(&self, signer: &S, writer: W) -> Result<()>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+        W: Write,
+    {
+        self.sign(signer)?.write_to(writer)
+    }
+
+    /// Like [`Package::export`], but compressing with a shared zstd dictionary.
+    /// The host needs the same dictionary to import the package.
+    pub fn export_with_dictionary<S>(&self, signer: &S, dict: &Dictionary) -> Result<Vec<u8>>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        self.sign_with_dictionary(signer, dict)?.to_bytes()
+    }
+
+    /// Re-sign an imported package with another key, e.g. during key rotation.
+    ///
+    /// The library and metadata are kept as is, the digest is not recomputed.
+    pub fn repack<S>(self, signer: &S) -> Result<Vec<u8>>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        self.export(signer)
+    }
+
+    /// Sign the package without serializing the resulting [`PackageExport`].
+    pub fn sign<S>(&self, signer: &S) -> Result<PackageExport>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        self.sign_with(signer, ExportAlgorithm::Zstd)
+    }
+
+    /// Like [`Package::sign`], but compressing the payload with `alog`.
+    pub fn sign_with<S>(&self, signer: &S, alog: ExportAlgorithm) -> Result<PackageExport>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        self.sign_encoded(signer, alog, None)
+    }
+
+    /// Like [`Package::sign`], but compressing with a shared zstd dictionary.
+    pub fn sign_with_dictionary<S>(&self, signer: &S, dict: &Dictionary) -> Result<PackageExport>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        self.sign_encoded(signer, ExportAlgorithm::ZstdDict(dict.id()), Some(dict))
+    }
+
+    fn sign_encoded<S>(
+        &self,
+        signer: &S,
+        alog: ExportAlgorithm,
+        dict: Option<&Dictionary>,
+    ) -> Result<PackageExport>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        let scheme = SignatureScheme::Ed25519;
+        let result = bincode_options().serialize(&self)?;
+        let signature = scheme.sign(signer, &result)?;
+
+        let compressed = alog.encode_with(result.as_slice(), dict)?;
+        // already compressed libraries only grow, those are stored as they are
+        let (alog, payload) = if alog != ExportAlgorithm::None && compressed.len() >= result.len() {
+            trace!(
+                "{:?} did not shrink the payload of {} bytes, storing it uncompressed",
+                alog,
+                result.len()
+            );
+            (ExportAlgorithm::None, result)
+        } else {
+            (alog, compressed)
         };
-        let exported = bincode::serialize(&exported)?;
-        Ok(exported)
+
+        Ok(PackageExport {
+            alog,
+            payload,
+            signature,
+            scheme,
+            format_version: FORMAT_VERSION,
+        })
     }
 
-    pub fn import_file<V, P: AsRef<Path>>(pathname: P, verifier: V) -> Result<Self>
+    pub fn import_file<V, P: AsRef<Path>>(pathname: P, verifier: &V) -> Result<Self>
     where
-        V: ed25519::signature::Verifier<Signature>,
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
     {
         let content = fs::read(pathname).map_err(Error::UnableToReadPackage)?;
         Self::import(content.as_slice(), verifier)
     }
 
-    pub fn import<V>(exported: &[u8], verifier: V) -> Result<Self>
+    pub fn import<V>(exported: &[u8], verifier: &V) -> Result<Self>
     where
-        V: ed25519::signature::Verifier<Signature>,
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
     {
-        let exported: PackageExport = bincode::deserialize(exported)?;
-        let payload = match exported.alog {
-            ExportAlgorithm::None => exported.payload,
-            ExportAlgorithm::Zstd => zstd::decode_all(exported.payload.as_slice())?,
-        };
-        verifier.verify(&payload, &exported.signature)?;
-        let package: Package = bincode::deserialize(&payload)?;
+        Ok(Self::import_verbose(exported, verifier)?.package)
+    }
+
+    /// Like [`Package::import`], for packages that may need one of `dictionaries`.
+    pub fn import_with_dictionaries<V>(
+        exported: &[u8],
+        verifier: &V,
+        dictionaries: &[Dictionary],
+    ) -> Result<Self>
+    where
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
+    {
+        Ok(Self::import_verbose_with(exported, verifier, dictionaries)?.package)
+    }
+
+    /// Like [`Package::import`], but keeps the signature and algorithm the package was
+    /// exported with, e.g. for audit logging.
+    pub fn import_verbose<V>(exported: &[u8], verifier: &V) -> Result<VerifiedPackage>
+    where
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
+    {
+        Self::import_verbose_with(exported, verifier, &[])
+    }
+
+    /// Like [`Package::import_verbose`], for packages that may need one of `dictionaries`.
+    pub fn import_verbose_with<V>(
+        exported: &[u8],
+        verifier: &V,
+        dictionaries: &[Dictionary],
+    ) -> Result<VerifiedPackage>
+    where
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
+    {
+        let exported = PackageExport::from_bytes(exported)?;
+        let package = exported.decode_package_verified(verifier, dictionaries)?;
         trace!("plugin {} contains valid signature", package.metadata.name);
         if package.digest_check() {
             trace!("plugin {} contains valid digest", package.metadata.name);
-            Ok(package)
+            Ok(VerifiedPackage {
+                package,
+                signature: exported.signature,
+                algorithm: exported.alog,
+                scheme: exported.scheme,
+            })
         } else {
             trace!("plugin {} contains invalid digest", package.metadata.name);
             Err(Error::InvalidDigest)
         }
     }
 
+    /// Check that a serialized package decodes and its library matches the embedded digest.
+    ///
+    /// The signature is *not* verified, this only detects truncation or corruption.
+    pub fn verify_integrity(exported: &[u8]) -> Result<bool> {
+        let (package, _) = PackageExport::from_bytes(exported)?.decode_package_unverified()?;
+        Ok(package.digest_check())
+    }
+
+    pub fn library_len(&self) -> usize {
+        self.library.len()
+    }
+
+    /// Read the metadata of a serialized package, without verifying its signature.
+    pub fn peek_metadata(exported: &[u8]) -> Result<PackageMetadata> {
+        let (package, _) = PackageExport::from_bytes(exported)?.decode_package_unverified()?;
+        Ok(package.metadata)
+    }
+
+    /// Report the sizes of a serialized package, without verifying its signature.
+    pub fn sizes(exported: &[u8]) -> Result<PackageSizes> {
+        let export = PackageExport::from_bytes(exported)?;
+        let (package, payload) = export.decode_package_unverified()?;
+        Ok(PackageSizes {
+            package: exported.len(),
+            compressed_payload: export.compressed_payload_len(),
+            payload,
+            library: package.library_len(),
+        })
+    }
+
+    /// Write the resources into `dir`, usually the temp dir the library was released to,
+    /// and return their paths.
+    ///
+    /// Resource names must be relative paths without `..`, so nothing is written outside `dir`.
+    pub fn release_resources_to_temp(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::with_capacity(self.resources.len());
+        for (name, content) in self.resources.iter() {
+            let relative = Path::new(name);
+            let valid = relative.components().next().is_some()
+                && relative
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)));
+            if !valid {
+                return Err(Error::InvalidResourceName(name.clone()));
+            }
+            let path = dir.join(relative);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, content)?;
+            trace!("release resource {} to: {:?}", name, path);
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
     pub fn release_lib_to_temp(&self) -> Result<(TempDir, PathBuf)> {
-        let temp_dir = tempfile::tempdir()?;
+        self.release_lib_to_temp_in(std::env::temp_dir())
+    }
+
+    /// Release the library into a new temp dir under `parent`.
+    ///
+    /// The temp dir is removed again if writing the library fails.
+    pub fn release_lib_to_temp_in<P: AsRef<Path>>(&self, parent: P) -> Result<(TempDir, PathBuf)> {
+        let temp_dir = tempfile::tempdir_in(parent)?;
         // generate a random name with extension
         let temp_dll_name = format!(
             "{}.{}",
@@ -169,29 +1047,104 @@ mod ser {
         where
             S: Serializer,
         {
-            let mut ser = serializer.serialize_struct("Package", 1)?;
+            let mut ser = serializer.serialize_struct("Package", 5)?;
             let meta_json = serde_json::to_string(&self.metadata).map_err(S::Error::custom)?;
             ser.serialize_field("bincode_version", BINCODE_VERSION)?;
             ser.serialize_field("abi_stable_version", ABI_STABLE_VERSION)?;
             ser.serialize_field("metadata", &meta_json)?;
             ser.serialize_field("library", &self.library)?;
+            ser.serialize_field("resources", &self.resources)?;
             ser.end()
         }
     }
 }
 
 mod de {
-    use super::Package;
+    use super::{bincode_options, Package, PackageExport, FORMAT_VERSION};
+    use bincode::Options;
     use plugin_commons::consts::*;
-    use serde::de::Error;
+    use serde::de::{Error, SeqAccess, Visitor};
     use serde::{Deserialize, Deserializer};
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::io::Read;
 
-    #[derive(Deserialize)]
     struct PackageDe {
         bincode_version: String,
         abi_stable_version: String,
         metadata: String,
         library: Vec<u8>,
+        resources: BTreeMap<String, Vec<u8>>,
+    }
+
+    struct PackageDeVisitor;
+
+    impl<'de> Visitor<'de> for PackageDeVisitor {
+        type Value = PackageDe;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a plugin package")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let missing = |field| A::Error::custom(format!("missing field {field}"));
+            Ok(PackageDe {
+                bincode_version: seq
+                    .next_element()?
+                    .ok_or_else(|| missing("bincode_version"))?,
+                abi_stable_version: seq
+                    .next_element()?
+                    .ok_or_else(|| missing("abi_stable_version"))?,
+                metadata: seq.next_element()?.ok_or_else(|| missing("metadata"))?,
+                library: seq.next_element()?.ok_or_else(|| missing("library"))?,
+                resources: seq.next_element()?.ok_or_else(|| missing("resources"))?,
+            })
+        }
+    }
+
+    const FIELDS: &[&str] = &[
+        "bincode_version",
+        "abi_stable_version",
+        "metadata",
+        "library",
+        "resources",
+    ];
+
+    struct PackageExportVisitor;
+
+    impl<'de> Visitor<'de> for PackageExportVisitor {
+        type Value = PackageExport;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a package envelope")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let missing = |field| A::Error::custom(format!("missing field {field}"));
+            Ok(PackageExport {
+                alog: seq.next_element()?.ok_or_else(|| missing("alog"))?,
+                payload: seq.next_element()?.ok_or_else(|| missing("payload"))?,
+                signature: seq.next_element()?.ok_or_else(|| missing("signature"))?,
+                scheme: seq.next_element()?.ok_or_else(|| missing("scheme"))?,
+                format_version: FORMAT_VERSION,
+            })
+        }
+    }
+
+    impl<'de> Deserialize<'de> for PackageExport {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            const FIELDS: &[&str] = &["alog", "payload", "signature", "scheme"];
+            deserializer.deserialize_struct("PackageExport", FIELDS, PackageExportVisitor)
+        }
     }
 
     impl<'de> Deserialize<'de> for Package {
@@ -199,36 +1152,657 @@ mod de {
         where
             D: Deserializer<'de>,
         {
-            let de = PackageDe::deserialize(deserializer)?;
-            if de.bincode_version != BINCODE_VERSION {
+            deserializer
+                .deserialize_struct("Package", FIELDS, PackageDeVisitor)?
+                .into_package()
+        }
+    }
+
+    /// Read the payload of a package of format version 1 or older, which ends after the
+    /// library if there are no resources.
+    pub(super) fn read_legacy_package<R: Read>(
+        mut reader: R,
+        limit: u64,
+    ) -> bincode::Result<Package> {
+        let (bincode_version, abi_stable_version, metadata, library) = bincode_options()
+            .with_limit(limit)
+            .deserialize_from(&mut reader)?;
+        let mut next = [0; 1];
+        let resources = if reader.read(&mut next)? == 0 {
+            BTreeMap::new()
+        } else {
+            bincode_options()
+                .with_limit(limit)
+                .deserialize_from(next.as_slice().chain(reader))?
+        };
+        PackageDe {
+            bincode_version,
+            abi_stable_version,
+            metadata,
+            library,
+            resources,
+        }
+        .into_package()
+    }
+
+    impl PackageDe {
+        fn into_package<E: Error>(self) -> Result<Package, E> {
+            if self.bincode_version != BINCODE_VERSION {
                 warn!(
                     "bincode version mismatch: expected {}, got {}",
-                    BINCODE_VERSION, de.bincode_version
+                    BINCODE_VERSION, self.bincode_version
                 );
                 #[cfg(feature = "strict")]
                 return Err(Error::custom(format!(
                     "bincode version mismatch: expected {}, got {}",
-                    BINCODE_VERSION, de.bincode_version
+                    BINCODE_VERSION, self.bincode_version
                 )));
             }
             trace!("bincode version matched");
-            if de.abi_stable_version != ABI_STABLE_VERSION {
+            if self.abi_stable_version != ABI_STABLE_VERSION {
                 warn!(
                     "abi stable version mismatch: expected {}, got {}",
-                    ABI_STABLE_VERSION, de.abi_stable_version
+                    ABI_STABLE_VERSION, self.abi_stable_version
                 );
                 #[cfg(feature = "strict")]
                 return Err(Error::custom(format!(
                     "abi stable version mismatch: expected {}, got {}",
-                    ABI_STABLE_VERSION, de.abi_stable_version
+                    ABI_STABLE_VERSION, self.abi_stable_version
                 )));
             }
             trace!("abi stable version matched");
-            let metadata = serde_json::from_str(&de.metadata).map_err(D::Error::custom)?;
-            Ok(Self {
-                metadata,
-                library: de.library,
-            })
+            let metadata = serde_json::from_str(&self.metadata).map_err(E::custom)?;
+            // the digest is checked by the import, not here
+            let mut package = Package::from_parts(metadata, self.library);
+            package.resources = self.resources;
+            package.embedded_bincode_version = Some(self.bincode_version);
+            package.embedded_abi_stable_version = Some(self.abi_stable_version);
+            Ok(package)
+        }
+    }
+}
+
+/// The key and the `spider` 1.0.0 package around `library` most tests start from.
+#[cfg(test)]
+pub(crate) fn spider(library: Vec<u8>) -> (ed25519_dalek::SigningKey, Package) {
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .build()
+        .unwrap();
+    let signer = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    (signer, Package::new(metadata, library))
+}
+
+#[test]
+fn test_export_is_reproducible() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    let first = package.export(&signer).unwrap();
+    let second = package.clone().export(&signer).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn test_verify_integrity_detects_corruption() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    let mut export = package.sign(&signer).unwrap();
+    assert!(Package::verify_integrity(&export.to_bytes().unwrap()).unwrap());
+
+    let mut tampered = package.clone();
+    tampered.library_mut()[0] ^= 0xff;
+    export.alog = ExportAlgorithm::None;
+    export.payload = bincode::serialize(&tampered).unwrap();
+    assert!(!Package::verify_integrity(&export.to_bytes().unwrap()).unwrap());
+}
+
+#[test]
+fn test_export_to_writer_matches_export() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    let mut streamed = Vec::new();
+    package.export_to_writer(&signer, &mut streamed).unwrap();
+    assert_eq!(streamed, package.export(&signer).unwrap());
+}
+
+#[test]
+fn test_truncated_package() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    let exported = package.export(&signer).unwrap();
+    let truncated = &exported[..exported.len() / 2];
+    assert!(matches!(
+        Package::import(truncated, &signer.verifying_key()),
+        Err(Error::TruncatedPackage)
+    ));
+}
+
+#[test]
+fn test_corrupt_envelope() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    let export = package.sign(&signer).unwrap();
+
+    let mut flipped = export.to_bytes().unwrap();
+    // the algorithm tag comes first after the header
+    flipped[PACKAGE_MAGIC.len() + 2] ^= 0x01;
+    assert!(matches!(
+        PackageExport::from_bytes(&flipped),
+        Err(Error::CorruptEnvelope)
+    ));
+
+    let legacy = bincode::serialize(&export).unwrap();
+    assert!(PackageExport::from_bytes(&legacy).is_ok());
+    // a header means the checksum is there too
+    let mut headed = flipped[..PACKAGE_MAGIC.len() + 2].to_vec();
+    headed.extend(&legacy);
+    assert!(matches!(
+        PackageExport::from_bytes(&headed),
+        Err(Error::CorruptEnvelope)
+    ));
+}
+
+#[test]
+fn test_export_uncompressed() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    let exported = package.export_with(&signer, ExportAlgorithm::None).unwrap();
+
+    let export = PackageExport::from_bytes(&exported).unwrap();
+    assert_eq!(export.alog, ExportAlgorithm::None);
+    assert_eq!(export.payload, bincode::serialize(&package).unwrap());
+
+    let imported = Package::import(&exported, &signer.verifying_key()).unwrap();
+    assert_eq!(imported.library(), package.library());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_incompressible_payload_is_stored_uncompressed() {
+    use rand::RngCore;
+
+    let mut library = vec![0; 4096];
+    thread_rng().fill_bytes(&mut library);
+    let (signer, package) = spider(library);
+    let export = package.sign(&signer).unwrap();
+    assert_eq!(export.alog, ExportAlgorithm::None);
+    assert_eq!(export.payload, bincode::serialize(&package).unwrap());
+    let imported = Package::import(&export.to_bytes().unwrap(), &signer.verifying_key()).unwrap();
+    assert_eq!(imported.library(), package.library());
+
+    let (_, package) = spider(vec![0xcd; 4096]);
+    assert_eq!(package.sign(&signer).unwrap().alog, ExportAlgorithm::Zstd);
+}
+
+#[test]
+fn test_digest_lengths_round_trip() {
+    let signer = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    for algorithm in [DigestAlgorithm::Blake512, DigestAlgorithm::Blake256] {
+        let metadata = PackageMetadata::builder()
+            .name("spider")
+            .version(Version::new(1, 0, 0))
+            .digest_algorithm(algorithm)
+            .build()
+            .unwrap();
+        let package = Package::new(metadata, vec![0xcd; 4096]);
+        assert_eq!(package.metadata.digest.len(), algorithm.len() * 2);
+
+        let exported = package.export(&signer).unwrap();
+        let imported = Package::import(&exported, &signer.verifying_key()).unwrap();
+        assert_eq!(imported.recorded_digest(), Some(package.digest()));
+    }
+}
+
+#[test]
+fn test_repack_with_new_key() {
+    let (old_key, package) = spider(vec![0xcd; 4096]);
+    let new_key = ed25519_dalek::SigningKey::from_bytes(&[8; 32]);
+    let exported = package.export(&old_key).unwrap();
+
+    let imported = Package::import(&exported, &old_key.verifying_key()).unwrap();
+    let repacked = imported.repack(&new_key).unwrap();
+    assert!(matches!(
+        Package::import(&repacked, &old_key.verifying_key()),
+        Err(Error::Signature(_))
+    ));
+    let imported = Package::import(&repacked, &new_key.verifying_key()).unwrap();
+    assert_eq!(imported.metadata, package.metadata);
+    assert_eq!(imported.library(), package.library());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_export_with_dictionary() {
+    let (signer, package) = spider(b"shared prefix of every spider".repeat(64));
+    // raw content dictionary, identified by its CRC32
+    let dict = Dictionary::new(b"shared prefix of every spider".to_vec());
+    let other = Dictionary::new(b"something else entirely".to_vec());
+
+    let exported = package.export_with_dictionary(&signer, &dict).unwrap();
+    let verifier = signer.verifying_key();
+    assert!(matches!(
+        Package::import(&exported, &verifier),
+        Err(Error::MissingDictionary(id)) if id == dict.id()
+    ));
+    assert!(matches!(
+        Package::import_with_dictionaries(&exported, &verifier, std::slice::from_ref(&other)),
+        Err(Error::MissingDictionary(_))
+    ));
+    let verified =
+        Package::import_verbose_with(&exported, &verifier, &[other, dict.clone()]).unwrap();
+    assert_eq!(verified.algorithm, ExportAlgorithm::ZstdDict(dict.id()));
+    assert_eq!(verified.package.library(), package.library());
+}
+
+#[test]
+fn test_metadata_notes_are_optional() {
+    let metadata: PackageMetadata =
+        serde_json::from_str(r#"{ "name": "spider", "version": "1.0.0" }"#).unwrap();
+    assert_eq!(metadata.description, None);
+    assert_eq!(metadata.changelog, None);
+
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 1, 0))
+        .description("crawls the web")
+        .changelog("- follow redirects")
+        .build()
+        .unwrap();
+    let json = serde_json::to_string(&metadata).unwrap();
+    assert_eq!(
+        serde_json::from_str::<PackageMetadata>(&json).unwrap(),
+        metadata
+    );
+}
+
+#[test]
+fn test_metadata_digest_detects_tampering() {
+    let (_, package) = spider(vec![0xcd; 4096]);
+    assert!(package.metadata.metadata_digest_check());
+    assert_eq!(
+        package.metadata.metadata_digest.as_deref(),
+        Some(package.metadata_digest().to_string().as_str())
+    );
+
+    // the library digest is not part of it
+    let mut rebuilt = package.metadata.clone();
+    rebuilt.digest = "00".to_string();
+    assert!(rebuilt.metadata_digest_check());
+
+    let mut tampered = package.metadata.clone();
+    tampered.version = Version::new(9, 0, 0);
+    assert!(!tampered.metadata_digest_check());
+
+    tampered.metadata_digest = None;
+    assert!(!tampered.metadata_digest_check());
+}
+
+#[test]
+fn test_from_parts_trusts_digest() {
+    let (_, package) = spider(vec![0xcd; 4096]);
+    let rebuilt = Package::from_parts(package.metadata.clone(), package.library().to_vec());
+    assert_eq!(rebuilt.metadata, package.metadata);
+    assert!(rebuilt.digest_check());
+
+    let swapped = Package::from_parts(package.metadata.clone(), vec![0xef; 4096]);
+    assert_eq!(swapped.metadata.digest, package.metadata.digest);
+    assert!(!swapped.digest_check());
+}
+
+#[test]
+fn test_resources_round_trip() {
+    let (signer, plain) = spider(vec![0xcd; 4096]);
+    let resources = BTreeMap::from([
+        ("config/default.toml".to_string(), b"depth = 3".to_vec()),
+        ("seeds.txt".to_string(), b"https://example.com".to_vec()),
+    ]);
+    let package = Package::with_resources(plain.metadata, plain.library, resources);
+    assert_ne!(package.digest(), package.library_digest());
+    assert_eq!(package.metadata.digest, package.digest().to_string());
+
+    let exported = package.export(&signer).unwrap();
+    let imported = Package::import(&exported, &signer.verifying_key()).unwrap();
+    assert_eq!(imported.resources, package.resources);
+    assert_eq!(imported.digest(), package.digest());
+
+    let dir = tempfile::tempdir().unwrap();
+    let paths = imported.release_resources_to_temp(dir.path()).unwrap();
+    assert_eq!(paths.len(), 2);
+    assert_eq!(
+        fs::read(dir.path().join("config/default.toml")).unwrap(),
+        b"depth = 3"
+    );
+
+    let mut escaping = imported;
+    escaping.resources = BTreeMap::from([("../escape".to_string(), vec![])]);
+    assert!(matches!(
+        escaping.release_resources_to_temp(dir.path()),
+        Err(Error::InvalidResourceName(name)) if name == "../escape"
+    ));
+}
+
+#[test]
+fn test_legacy_package_without_resources() {
+    use ed25519_dalek::Signer;
+    use plugin_commons::consts::{ABI_STABLE_VERSION, BINCODE_VERSION};
+
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    assert_eq!(package.digest(), package.library_digest());
+
+    let legacy = bincode::serialize(&(
+        BINCODE_VERSION,
+        ABI_STABLE_VERSION,
+        serde_json::to_string(&package.metadata).unwrap(),
+        &package.library(),
+    ))
+    .unwrap();
+    // the current format always records the resources
+    let mut current = legacy.clone();
+    current.extend(bincode::serialize(&BTreeMap::<String, Vec<u8>>::new()).unwrap());
+    assert_eq!(bincode::serialize(&package).unwrap(), current);
+    assert!(bincode::deserialize::<Package>(&legacy).is_err());
+
+    // format version 1 payloads end after the library
+    let export = |payload: Vec<u8>| {
+        PackageExport {
+            alog: ExportAlgorithm::None,
+            signature: signer.sign(&payload),
+            payload,
+            scheme: SignatureScheme::Ed25519,
+            format_version: LEGACY_FORMAT_VERSION,
         }
+        .to_bytes()
+        .unwrap()
+    };
+    let exported = export(legacy.clone());
+    assert_eq!(exported[4..6], LEGACY_FORMAT_VERSION.to_le_bytes());
+    let decoded = Package::import(&exported, &signer.verifying_key()).unwrap();
+    assert!(decoded.resources.is_empty());
+    assert_eq!(decoded.library(), package.library());
+    assert_eq!(
+        Package::peek_metadata(&exported).unwrap().name,
+        package.metadata.name
+    );
+
+    // only missing resources default to none, a malformed section is an error
+    let mut malformed = legacy;
+    malformed.extend(bincode::serialize(&BTreeMap::from([(vec![0xffu8], vec![0u8])])).unwrap());
+    assert!(Package::import(&export(malformed), &signer.verifying_key()).is_err());
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+    use semver::{Comparator, Op};
+
+    const ALGORITHMS: [ExportAlgorithm; 2] = [ExportAlgorithm::None, ExportAlgorithm::Zstd];
+
+    fn version() -> impl Strategy<Value = Version> {
+        (0..100u64, 0..100u64, 0..100u64)
+            .prop_map(|(major, minor, patch)| Version::new(major, minor, patch))
     }
+
+    fn metadata() -> impl Strategy<Value = PackageMetadata> {
+        (
+            "[a-z][a-z0-9_-]{0,15}",
+            version(),
+            proptest::option::of(version()),
+            prop_oneof![
+                Just(DigestAlgorithm::Blake512),
+                Just(DigestAlgorithm::Blake512Tree),
+                Just(DigestAlgorithm::Blake256)
+            ],
+            proptest::collection::btree_map("[A-Z]{1,8}", version(), 0..4),
+        )
+            .prop_map(
+                |(name, version, plugin_base_version, digest_algorithm, deps)| PackageMetadata {
+                    target: None,
+                    description: None,
+                    changelog: None,
+                    priority: 0,
+                    id: None,
+                    metadata_digest: None,
+                    name,
+                    digest: String::new(),
+                    digest_algorithm,
+                    version,
+                    plugin_base_version,
+                    dependencies: deps
+                        .into_iter()
+                        .map(|(name, version)| DependencySpec {
+                            name,
+                            version: VersionReq {
+                                comparators: vec![Comparator {
+                                    op: Op::Caret,
+                                    major: version.major,
+                                    minor: Some(version.minor),
+                                    patch: Some(version.patch),
+                                    pre: version.pre,
+                                }],
+                            },
+                            id: None,
+                        })
+                        .collect(),
+                },
+            )
+    }
+
+    fn package() -> impl Strategy<Value = Package> {
+        (
+            metadata(),
+            proptest::collection::vec(any::<u8>(), 0..16 * 1024),
+        )
+            .prop_map(|(metadata, library)| Package::new(metadata, library))
+    }
+
+    fn signer(seed: [u8; 32]) -> ed25519_dalek::SigningKey {
+        ed25519_dalek::SigningKey::from_bytes(&seed)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn export_import_round_trip(package in package(), seed in any::<[u8; 32]>()) {
+            let signer = signer(seed);
+            for alog in ALGORITHMS {
+                let exported = package.export_with(&signer, alog).unwrap();
+                let verified = Package::import_verbose(&exported, &signer.verifying_key()).unwrap();
+                // payloads compression doesn't shrink are stored uncompressed
+                prop_assert!(verified.algorithm == alog || verified.algorithm == ExportAlgorithm::None);
+                prop_assert_eq!(&verified.package.metadata, &package.metadata);
+                prop_assert_eq!(&verified.package.library(), &package.library());
+            }
+        }
+
+        #[test]
+        fn tampered_payload_is_rejected(
+            package in package(),
+            seed in any::<[u8; 32]>(),
+            index in any::<prop::sample::Index>(),
+            flip in 1..=u8::MAX,
+        ) {
+            let signer = signer(seed);
+            for alog in ALGORITHMS {
+                let mut export = package.sign_with(&signer, alog).unwrap();
+                let i = index.index(export.payload.len());
+                export.payload[i] ^= flip;
+                let tampered = export.to_bytes().unwrap();
+                prop_assert!(Package::import(&tampered, &signer.verifying_key()).is_err());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_wire_format_is_little_endian() {
+    let export = PackageExport {
+        alog: ExportAlgorithm::ZstdDict(0x0a0b_0c0d),
+        payload: vec![1, 2, 3],
+        signature: Signature::from_bytes(&[0x55; 64]),
+        scheme: SignatureScheme::Ed25519,
+        format_version: FORMAT_VERSION,
+    };
+    // written out by hand so it decodes the same on any host
+    let mut golden = vec![2, 0, 0, 0, 0x0d, 0x0c, 0x0b, 0x0a];
+    golden.extend([3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+    golden.extend([0x55; 64]);
+    golden.extend([0, 0, 0, 0]);
+
+    let bytes = bincode_options().serialize(&export).unwrap();
+    assert_eq!(bytes, golden);
+    assert_eq!(bytes, bincode::serialize(&export).unwrap());
+
+    let decoded: PackageExport = bincode_options().deserialize(&golden).unwrap();
+    assert_eq!(decoded.alog, export.alog);
+    assert_eq!(decoded.payload, export.payload);
+    assert_eq!(decoded.signature, export.signature);
+    assert_eq!(decoded.scheme, export.scheme);
+}
+
+#[test]
+fn test_import_keeps_embedded_versions() {
+    use plugin_commons::consts::{ABI_STABLE_VERSION, BINCODE_VERSION};
+
+    let (signer, package) = spider(vec![0xcd; 64]);
+    assert_eq!(package.embedded_bincode_version, None);
+
+    let exported = package.export(&signer).unwrap();
+    let imported = Package::import(&exported, &signer.verifying_key()).unwrap();
+    assert_eq!(
+        imported.embedded_bincode_version.as_deref(),
+        Some(BINCODE_VERSION)
+    );
+    assert_eq!(
+        imported.embedded_abi_stable_version.as_deref(),
+        Some(ABI_STABLE_VERSION)
+    );
+}
+
+#[test]
+fn test_envelope_records_scheme() {
+    let (signer, package) = spider(vec![0xcd; 64]);
+    let export = package.sign(&signer).unwrap();
+    assert_eq!(export.scheme, SignatureScheme::Ed25519);
+
+    // the current format always records the scheme
+    let legacy = bincode::serialize(&(export.alog, &export.payload, export.signature)).unwrap();
+    let mut explicit = legacy.clone();
+    explicit.extend(0u32.to_le_bytes());
+    assert_eq!(bincode_options().serialize(&export).unwrap(), explicit);
+    assert!(bincode_options()
+        .deserialize::<PackageExport>(&legacy)
+        .is_err());
+
+    // headerless envelopes written before it end after the signature
+    let parsed = PackageExport::from_bytes(&legacy).unwrap();
+    assert_eq!(parsed.scheme, SignatureScheme::Ed25519);
+    assert_eq!(parsed.payload, export.payload);
+    // only a missing scheme defaults to ed25519
+    let mut unknown = legacy.clone();
+    unknown.extend(1u32.to_le_bytes());
+    assert!(PackageExport::from_bytes(&unknown).is_err());
+
+    let verified =
+        Package::import_verbose(&export.to_bytes().unwrap(), &signer.verifying_key()).unwrap();
+    assert_eq!(verified.scheme, SignatureScheme::Ed25519);
+}
+
+#[test]
+fn test_format_header() {
+    let (signer, package) = spider(vec![0xcd; 64]);
+    let export = package.sign(&signer).unwrap();
+    let exported = export.to_bytes().unwrap();
+    assert_eq!(&exported[..4], PACKAGE_MAGIC);
+    assert_eq!(exported[4..6], FORMAT_VERSION.to_le_bytes());
+
+    let mut future = exported.clone();
+    future[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        PackageExport::from_bytes(&future),
+        Err(Error::UnsupportedFormatVersion(v)) if v == FORMAT_VERSION + 1
+    ));
+    assert!(matches!(
+        PackageExport::from_bytes(b"\x7fELF not a package at all"),
+        Err(Error::BadMagic)
+    ));
+
+    // packages written before the header existed
+    let headerless = exported[6..].to_vec();
+    assert!(PackageExport::from_bytes(&headerless).is_ok());
+}
+
+#[test]
+fn test_library_digest_cache() {
+    let (_, mut package) = spider(vec![0xcd; 64]);
+    let digest = package.library_digest();
+    assert!(package.digest_check());
+
+    package.metadata.digest_algorithm = DigestAlgorithm::Blake256;
+    assert_eq!(
+        package.library_digest(),
+        DigestAlgorithm::Blake256.digest(package.library())
+    );
+    package.metadata.digest_algorithm = DigestAlgorithm::Blake512;
+    assert_eq!(package.library_digest(), digest);
+
+    package.library_mut()[0] ^= 0xff;
+    assert_ne!(package.library_digest(), digest);
+    assert!(!package.digest_check());
+}
+
+#[test]
+fn test_sizes_stream_the_payload() {
+    let (signer, package) = spider(vec![0xcd; 4096]);
+    for alog in [ExportAlgorithm::None, ExportAlgorithm::Zstd] {
+        let export = package.sign_with(&signer, alog).unwrap();
+        let exported = export.to_bytes().unwrap();
+        let sizes = Package::sizes(&exported).unwrap();
+        assert_eq!(sizes.payload, export.decode_payload().unwrap().len());
+        assert_eq!(sizes.library, 4096);
+        assert!(export.alog.decoded_len_bound(&export.payload).unwrap() >= sizes.payload as u64);
+        assert!(Package::verify_integrity(&exported).unwrap());
+    }
+}
+
+#[test]
+fn test_length_prefix_beyond_payload() {
+    let (signer, _) = spider(Vec::new());
+    // the first field claims a 1 TiB string, the payload ends right after
+    let mut payload = (1u64 << 40).to_le_bytes().to_vec();
+    payload.extend_from_slice(&[0xcd; 64]);
+    for alog in [ExportAlgorithm::None, ExportAlgorithm::Zstd] {
+        let export = PackageExport {
+            alog,
+            payload: alog.encode(&payload).unwrap(),
+            signature: ed25519_dalek::Signer::sign(&signer, &payload),
+            scheme: SignatureScheme::Ed25519,
+            format_version: FORMAT_VERSION,
+        };
+        let exported = export.to_bytes().unwrap();
+        assert!(Package::peek_metadata(&exported).is_err());
+        assert!(Package::verify_integrity(&exported).is_err());
+        assert!(Package::sizes(&exported).is_err());
+        assert!(matches!(
+            Package::import(&exported, &signer.verifying_key()),
+            Err(Error::Bincode(_))
+        ));
+    }
+}
+
+#[test]
+fn test_default_fields_keep_metadata_digest() {
+    let metadata = PackageMetadata::builder()
+        .name("spider")
+        .version(Version::new(1, 0, 0))
+        .build()
+        .unwrap();
+    let json = serde_json::to_value(&metadata).unwrap();
+    assert!(json.get("priority").is_none());
+    assert!(json.get("id").is_none());
+
+    let prioritized = PackageMetadata {
+        priority: 10,
+        ..metadata.clone()
+    };
+    let json = serde_json::to_string(&prioritized).unwrap();
+    let parsed: PackageMetadata = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.priority, 10);
+    assert_ne!(prioritized.metadata_digest(), metadata.metadata_digest());
 }
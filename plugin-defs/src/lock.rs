@@ -0,0 +1,133 @@
+use crate::{Error, PackageMetadata, Result};
+use ed25519::Signature;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The exact plugin builds making up a deployment, like a `Cargo.lock` for plugins.
+///
+/// Plugins are keyed by their [`PackageMetadata::id`], or by name for packages without one.
+/// Signed, it is stored as JSON next to its hex signature:
+///
+/// ```json
+/// { "plugins": { "spider": { "version": "1.0.0", "digest": "9f3c..." } }, "signature": "..." }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginLock {
+    pub plugins: BTreeMap<String, LockedPlugin>,
+}
+
+/// Locked build of a plugin.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPlugin {
+    pub version: Version,
+    /// hex digest of the library, as recorded in [`PackageMetadata::digest`]
+    pub digest: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedLock {
+    plugins: BTreeMap<String, LockedPlugin>,
+    signature: String,
+}
+
+impl PluginLock {
+    /// Key of a package in the lock: its id, or its name for packages without one.
+    pub fn key(metadata: &PackageMetadata) -> &str {
+        metadata.id.as_deref().unwrap_or(&metadata.name)
+    }
+
+    /// Lock the build a package was made from.
+    pub fn insert(&mut self, metadata: &PackageMetadata) {
+        self.plugins.insert(
+            Self::key(metadata).to_string(),
+            LockedPlugin {
+                version: metadata.version.clone(),
+                digest: metadata.digest.clone(),
+            },
+        );
+    }
+
+    /// Whether the lock lists exactly this build.
+    pub fn matches(&self, metadata: &PackageMetadata) -> bool {
+        self.plugins.get(Self::key(metadata)).is_some_and(|locked| {
+            locked.version == metadata.version
+                && locked.digest.eq_ignore_ascii_case(&metadata.digest)
+        })
+    }
+
+    /// Sign the lock and serialize it with its signature.
+    pub fn sign<S>(&self, signer: &S) -> Result<Vec<u8>>
+    where
+        S: ed25519::signature::Signer<Signature> + ?Sized,
+    {
+        let signature = signer.try_sign(&self.signed_bytes()?)?;
+        let signed = SignedLock {
+            plugins: self.plugins.clone(),
+            signature: hex::encode(signature.to_bytes()),
+        };
+        serde_json::to_vec_pretty(&signed).map_err(|e| Error::InvalidLock(e.to_string()))
+    }
+
+    /// Parse a lock written by [`PluginLock::sign`] and check its signature.
+    pub fn verify<V>(signed: &[u8], verifier: &V) -> Result<Self>
+    where
+        V: ed25519::signature::Verifier<Signature> + ?Sized,
+    {
+        let signed: SignedLock =
+            serde_json::from_slice(signed).map_err(|e| Error::InvalidLock(e.to_string()))?;
+        let signature = hex::decode(&signed.signature)
+            .map_err(|e| Error::InvalidLock(e.to_string()))
+            .and_then(|bytes| Ok(Signature::from_slice(&bytes)?))?;
+        let lock = PluginLock {
+            plugins: signed.plugins,
+        };
+        verifier.verify(&lock.signed_bytes()?, &signature)?;
+        Ok(lock)
+    }
+
+    /// The signed message: the lock as JSON, with keys in a fixed order.
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| Error::InvalidLock(e.to_string()))
+    }
+}
+
+#[test]
+fn test_lock_sign_verify() {
+    let (signer, package) = crate::spider(vec![0xcd; 4096]);
+    let other = ed25519_dalek::SigningKey::from_bytes(&[8; 32]);
+
+    let mut lock = PluginLock::default();
+    lock.insert(&package.metadata);
+    assert!(lock.matches(&package.metadata));
+    let signed = lock.sign(&signer).unwrap();
+    assert_eq!(
+        PluginLock::verify(&signed, &signer.verifying_key()).unwrap(),
+        lock
+    );
+    assert!(matches!(
+        PluginLock::verify(&signed, &other.verifying_key()),
+        Err(Error::Signature(_))
+    ));
+
+    // bumping a locked version invalidates the signature
+    let tampered = String::from_utf8(signed).unwrap().replace("1.0.0", "1.0.1");
+    assert!(matches!(
+        PluginLock::verify(tampered.as_bytes(), &signer.verifying_key()),
+        Err(Error::Signature(_))
+    ));
+
+    let (_, rebuilt) = crate::spider(vec![0xef; 4096]);
+    assert!(!lock.matches(&rebuilt.metadata));
+
+    // plugins sharing a name are locked apart by id
+    let other_vendor = PackageMetadata {
+        id: Some("org.other.spider".to_string()),
+        ..package.metadata.clone()
+    };
+    assert!(!lock.matches(&other_vendor));
+    lock.insert(&other_vendor);
+    assert!(lock.matches(&package.metadata));
+    assert!(lock.matches(&other_vendor));
+    assert_eq!(lock.plugins.len(), 2);
+}
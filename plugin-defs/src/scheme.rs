@@ -0,0 +1,73 @@
+use crate::Result;
+use ed25519::signature::{Signer, Verifier};
+use ed25519::Signature;
+use serde::{Deserialize, Serialize};
+
+/// Algorithm a [`PackageExport`](crate::PackageExport) is signed with.
+///
+/// Recorded in the envelope so schemes required by other PKIs can be added next to
+/// ed25519 without changing the package format again. Each variant dispatches to a
+/// [`SchemeImpl`], only ed25519 is implemented.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    #[default]
+    Ed25519,
+}
+
+/// Signing and verification of package payloads for one [`SignatureScheme`].
+pub trait SchemeImpl {
+    /// Sign a package payload.
+    fn sign<S>(&self, signer: &S, payload: &[u8]) -> Result<Signature>
+    where
+        S: Signer<Signature> + ?Sized;
+
+    /// Check the signature of a package payload.
+    fn verify<V>(&self, verifier: &V, payload: &[u8], signature: &Signature) -> Result<()>
+    where
+        V: Verifier<Signature> + ?Sized;
+}
+
+/// [`SignatureScheme::Ed25519`]
+pub struct Ed25519Scheme;
+
+impl SchemeImpl for Ed25519Scheme {
+    fn sign<S>(&self, signer: &S, payload: &[u8]) -> Result<Signature>
+    where
+        S: Signer<Signature> + ?Sized,
+    {
+        Ok(signer.try_sign(payload)?)
+    }
+
+    fn verify<V>(&self, verifier: &V, payload: &[u8], signature: &Signature) -> Result<()>
+    where
+        V: Verifier<Signature> + ?Sized,
+    {
+        Ok(verifier.verify(payload, signature)?)
+    }
+}
+
+impl SignatureScheme {
+    pub fn is_ed25519(&self) -> bool {
+        *self == SignatureScheme::Ed25519
+    }
+
+    /// Sign a package payload with a key of this scheme.
+    pub fn sign<S>(self, signer: &S, payload: &[u8]) -> Result<Signature>
+    where
+        S: Signer<Signature> + ?Sized,
+    {
+        match self {
+            SignatureScheme::Ed25519 => Ed25519Scheme.sign(signer, payload),
+        }
+    }
+
+    /// Check the signature of a package payload with a key of this scheme.
+    pub fn verify<V>(self, verifier: &V, payload: &[u8], signature: &Signature) -> Result<()>
+    where
+        V: Verifier<Signature> + ?Sized,
+    {
+        match self {
+            SignatureScheme::Ed25519 => Ed25519Scheme.verify(verifier, payload, signature),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{anyhow, bail};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use pem::Pem;
+use std::fs;
+use std::path::Path;
+
+const PUBLIC_KEY_TAG: &str = "ED25519 DALEK PUBLIC KEY";
+const KEY_TAG: &str = "ED25519 DALEK KEY";
+
+pub fn public_key_pem(key: &VerifyingKey) -> String {
+    pem::encode(&Pem {
+        tag: PUBLIC_KEY_TAG.to_string(),
+        contents: key.as_bytes().to_vec(),
+    })
+}
+
+pub fn key_pem(key: &SigningKey) -> String {
+    pem::encode(&Pem {
+        tag: KEY_TAG.to_string(),
+        contents: key.to_bytes().to_vec(),
+    })
+}
+
+pub fn read_signing_key<P: AsRef<Path>>(path: P) -> anyhow::Result<SigningKey> {
+    let pem = pem::parse(fs::read(path.as_ref())?)?;
+    if pem.tag != KEY_TAG {
+        bail!(
+            "{:?} is not a private key (found {})",
+            path.as_ref(),
+            pem.tag
+        );
+    }
+    let bytes = pem
+        .contents
+        .try_into()
+        .map_err(|_| anyhow!("{:?} has an invalid key length", path.as_ref()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Build a verifying key from its raw bytes, e.g. decoded from a CI secret.
+pub fn verifying_key_from_bytes(bytes: &[u8]) -> anyhow::Result<VerifyingKey> {
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| anyhow!("a public key is 32 bytes, got {}", bytes.len()))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Read a verifying key from either a public or a private key PEM.
+pub fn read_verifying_key<P: AsRef<Path>>(path: P) -> anyhow::Result<VerifyingKey> {
+    let pem = pem::parse(fs::read(path.as_ref())?)?;
+    match pem.tag.as_str() {
+        KEY_TAG => Ok(read_signing_key(path)?.verifying_key()),
+        PUBLIC_KEY_TAG => {
+            let bytes = pem
+                .contents
+                .try_into()
+                .map_err(|_| anyhow!("{:?} has an invalid key length", path.as_ref()))?;
+            Ok(VerifyingKey::from_bytes(&bytes)?)
+        }
+        tag => bail!("{:?} has an unknown PEM tag {}", path.as_ref(), tag),
+    }
+}
@@ -1,11 +1,19 @@
 use anyhow::bail;
-use clap::{Parser, Subcommand};
-use ed25519_dalek::SigningKey;
-use pem::Pem;
-use plugin_defs::{Package, PackageMetadata};
+use cargo_metadata::semver::VersionReq;
+use cargo_metadata::MetadataCommand;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use plugin_commons::consts::TARGET;
+use plugin_defs::{
+    fingerprint, Dictionary, ExportAlgorithm, LibraryFormat, Package, PackageMetadata, PluginLock,
+};
 use rand::thread_rng;
+use std::collections::BTreeMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::{env, fs};
+use std::{env, fs, io};
+
+mod keys;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -19,71 +27,420 @@ enum Commands {
     GenKeypair {
         #[clap(value_parser)]
         output: Option<String>,
+        /// Overwrite existing key files
+        #[clap(long)]
+        force: bool,
+        /// Only write public-key.pem, derived from this existing private key
+        #[clap(long, value_name = "KEY", value_parser)]
+        pubkey_only: Option<String>,
+    },
+    /// Print the fingerprint of a public or private key
+    Fingerprint {
+        #[clap(value_parser)]
+        key: String,
     },
-    Pack {
+    Pack(PackArgs),
+    /// Re-sign a package with a new key, after verifying it with the old one
+    Resign {
         #[clap(value_parser)]
-        library: String,
+        file: String,
+        /// Public (or private) key the package is currently signed with
+        #[clap(long, value_name = "PUB", value_parser)]
+        old_key: String,
+        /// Private key to sign the package with
+        #[clap(long, value_name = "KEY", value_parser)]
+        new_key: String,
+        /// Write the re-signed package here instead of replacing the input
         #[clap(short, long, value_parser)]
-        metadata: Option<String>,
+        output: Option<String>,
+        /// zstd dictionary the package was compressed with, if any
+        #[clap(long, value_name = "DICT", value_parser)]
+        dict: Option<String>,
+    },
+    /// Re-sign every package in a directory with a new key, after verifying each with the old one
+    Rotate {
+        /// Public (or private) key the packages are currently signed with
+        #[clap(long, value_name = "PUB", value_parser)]
+        old_pub: String,
+        /// Private key to sign the packages with
+        #[clap(long, value_name = "KEY", value_parser)]
+        new_key: String,
+        /// Directory of `.cdp` packages
+        #[clap(long, value_parser)]
+        dir: String,
+        /// Directory to write the re-signed packages to instead of replacing them
+        #[clap(short, long, value_parser)]
+        output: Option<String>,
+        /// zstd dictionary the packages were compressed with, if any
+        #[clap(long, value_name = "DICT", value_parser)]
+        dict: Option<String>,
+    },
+    /// Write a signed lock listing exactly the builds of these packages
+    Lock {
+        #[clap(value_parser, required = true)]
+        packages: Vec<String>,
+        /// Private key to sign the lock with, defaults to ./key.pem
         #[clap(short, long, value_parser)]
         key: Option<String>,
+        /// Public (or private) key the packages are signed with, defaults to the lock key
+        #[clap(long, value_name = "PUB", value_parser)]
+        package_key: Option<String>,
+        /// zstd dictionary the packages were compressed with, if any
+        #[clap(long, value_name = "DICT", value_parser)]
+        dict: Option<String>,
+        #[clap(short, long, value_parser, default_value = "plugins.lock")]
+        output: String,
+    },
+    /// Verify a package and print its metadata, description and changelog
+    Inspect {
+        #[clap(value_parser)]
+        file: String,
+        /// Public (or private) key the package is signed with, defaults to ./public-key.pem
         #[clap(short, long, value_parser)]
-        output: Option<String>,
+        key: Option<String>,
+        /// Public key the package is signed with, as hex instead of a PEM file
+        #[clap(long, value_name = "HEX", value_parser, conflicts_with = "key")]
+        pubkey_hex: Option<String>,
+        /// Public key the package is signed with, as base64 instead of a PEM file
+        #[clap(
+            long,
+            value_name = "BASE64",
+            value_parser,
+            conflicts_with_all = ["key", "pubkey_hex"]
+        )]
+        pubkey_base64: Option<String>,
+        /// zstd dictionary the package was compressed with, if any
+        #[clap(long, value_name = "DICT", value_parser)]
+        dict: Option<String>,
+        /// Print the metadata as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Verify many packages and print a table of the results, failing if any doesn't verify
+    VerifyAll {
+        /// Packages to verify, read one per line from stdin if none are given
+        #[clap(value_parser)]
+        files: Vec<String>,
+        /// Public (or private) key the packages are signed with, defaults to ./public-key.pem
+        #[clap(short, long, value_parser)]
+        key: Option<String>,
+        /// zstd dictionary the packages were compressed with, if any
+        #[clap(long, value_name = "DICT", value_parser)]
+        dict: Option<String>,
+        /// Print the results as JSON
+        #[clap(long)]
+        json: bool,
     },
 }
 
+#[derive(Args)]
+struct PackArgs {
+    #[clap(value_parser)]
+    library: String,
+    #[clap(short, long, value_parser)]
+    metadata: Option<String>,
+    /// Read name, version and `[package.metadata.comet]` dependencies from a Cargo.toml
+    #[clap(long, value_parser, conflicts_with = "metadata")]
+    from_manifest: Option<String>,
+    #[clap(short, long, value_parser)]
+    key: Option<String>,
+    /// Output file, or directory to write `<name>.cdp` to; defaults to the library directory
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
+    /// Print the pack result as JSON
+    #[clap(long)]
+    json: bool,
+    /// How the payload is compressed, `none` e.g. when the library is already compressed
+    #[clap(long, value_enum, default_value_t = Algorithm::Zstd)]
+    algorithm: Algorithm,
+    /// Store the library uncompressed, same as `--algorithm none`
+    #[clap(long, conflicts_with = "algorithm")]
+    no_compress: bool,
+    /// Compress with this zstd dictionary, the host must be given the same one
+    #[clap(
+        long,
+        value_name = "DICT",
+        value_parser,
+        conflicts_with_all = ["no_compress", "algorithm"]
+    )]
+    dict: Option<String>,
+    /// Fail instead of warning if the library doesn't look like a native library, and
+    /// reject a 0.0.0 version or dependencies on any version
+    #[clap(long)]
+    strict: bool,
+    /// Don't warn about a pre-release version with --strict
+    #[clap(long)]
+    allow_prerelease: bool,
+    /// Target triple the library was built for, defaults to the one plugin-pack was built for
+    #[clap(long, value_name = "TRIPLE", value_parser)]
+    target: Option<String>,
+    /// Ship a data file with the library, released next to it on load
+    #[clap(long = "resource", value_name = "NAME=FILE", value_parser = parse_resource)]
+    resources: Vec<(String, PathBuf)>,
+}
+
+/// The [`ExportAlgorithm`]s a package can be packed with from the command line,
+/// dictionaries are chosen with `--dict` instead.
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Algorithm {
+    None,
+    Zstd,
+}
+
+impl From<Algorithm> for ExportAlgorithm {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::None => ExportAlgorithm::None,
+            Algorithm::Zstd => ExportAlgorithm::Zstd,
+        }
+    }
+}
+
+fn parse_resource(arg: &str) -> Result<(String, PathBuf), String> {
+    let (name, file) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=FILE, got `{arg}`"))?;
+    Ok((name.to_string(), PathBuf::from(file)))
+}
+
+/// What `pack` produced, for provenance records.
+struct PackReport {
+    output: PathBuf,
+    name: String,
+    version: String,
+    digest: String,
+    signature: String,
+    algorithm: ExportAlgorithm,
+    /// size of the library before compression
+    library_size: usize,
+    /// size of the signed payload as stored in the package
+    compressed_size: usize,
+}
+
+impl PackReport {
+    /// Compressed size as a fraction of the library size.
+    fn ratio(&self) -> f64 {
+        if self.library_size == 0 {
+            return 1.0;
+        }
+        self.compressed_size as f64 / self.library_size as f64
+    }
+
+    fn print(&self, json: bool) {
+        if json {
+            let value = serde_json::json!({
+                "output": self.output,
+                "name": self.name,
+                "version": self.version,
+                "digest": self.digest,
+                "signature": self.signature,
+                "algorithm": format!("{:?}", self.algorithm),
+                "library_size": self.library_size,
+                "compressed_size": self.compressed_size,
+                "ratio": self.ratio(),
+            });
+            println!("{value}");
+        } else {
+            println!("packed {} {} -> {:?}", self.name, self.version, self.output);
+            println!("digest: {}", self.digest);
+            println!("signature: {}", self.signature);
+            println!("compression: {:?}", self.algorithm);
+            println!(
+                "size: {} -> {} ({:.1}%)",
+                human_size(self.library_size),
+                human_size(self.compressed_size),
+                self.ratio() * 100.0
+            );
+        }
+    }
+}
+
+/// Decimal units, e.g. `42.1 MB`.
+fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
 fn main() -> anyhow::Result<()> {
     let cli: Cli = Cli::parse();
 
     match cli.command {
-        Commands::GenKeypair { output } => gen_keypair(output),
-        Commands::Pack {
-            library,
-            metadata,
+        Commands::GenKeypair {
+            output,
+            force,
+            pubkey_only,
+        } => gen_keypair(output, force, pubkey_only),
+        Commands::Fingerprint { key } => {
+            check_file_exist(&key)?;
+            let key = keys::read_verifying_key(key)?;
+            println!("{}", fingerprint(key.as_bytes()));
+            Ok(())
+        }
+        Commands::Pack(args) => {
+            let json = args.json;
+            let report = pack(args)?;
+            report.print(json);
+            Ok(())
+        }
+        Commands::Resign {
+            file,
+            old_key,
+            new_key,
+            output,
+            dict,
+        } => resign(file, old_key, new_key, output, dict),
+        Commands::Rotate {
+            old_pub,
+            new_key,
+            dir,
+            output,
+            dict,
+        } => rotate(old_pub, new_key, dir, output, dict),
+        Commands::Lock {
+            packages,
             key,
+            package_key,
+            dict,
             output,
-        } => pack(library, metadata, key, output),
+        } => lock(packages, key, package_key, dict, output),
+        Commands::Inspect {
+            file,
+            key,
+            pubkey_hex,
+            pubkey_base64,
+            dict,
+            json,
+        } => {
+            let key = if let Some(key) = pubkey_hex {
+                keys::verifying_key_from_bytes(&hex::decode(key.trim())?)?
+            } else if let Some(key) = pubkey_base64 {
+                keys::verifying_key_from_bytes(&base64::decode(key.trim())?)?
+            } else {
+                read_verifying_key_or_default(key)?
+            };
+            inspect(file, &key, dict, json)
+        }
+        Commands::VerifyAll {
+            files,
+            key,
+            dict,
+            json,
+        } => verify_all(files, &read_verifying_key_or_default(key)?, dict, json),
     }
 }
 
-fn gen_keypair(output_path: Option<String>) -> anyhow::Result<()> {
+/// Read a public (or private) key, ./public-key.pem if none is given.
+fn read_verifying_key_or_default(key: Option<String>) -> anyhow::Result<VerifyingKey> {
+    let key = if let Some(path) = key {
+        PathBuf::from(path)
+    } else {
+        env::current_dir()?.join("public-key.pem")
+    };
+    check_file_exist(&key)?;
+    keys::read_verifying_key(key)
+}
+
+fn gen_keypair(
+    output_path: Option<String>,
+    force: bool,
+    pubkey_only: Option<String>,
+) -> anyhow::Result<()> {
     let output_path = if let Some(path) = output_path {
         PathBuf::from(path)
     } else {
         env::current_dir()?
     };
-    fs::create_dir_all(&output_path)?;
-    let keypair = SigningKey::generate(&mut thread_rng());
-    let pubkey = Pem {
-        tag: "ed25519 dalek public key".to_uppercase(),
-        contents: keypair.verifying_key().as_bytes().to_vec(),
+    let pubkey_path = output_path.join("public-key.pem");
+    let key_path = output_path.join("key.pem");
+    let keypair = if let Some(key) = pubkey_only {
+        check_file_exist(&key)?;
+        Some(keys::read_signing_key(key)?)
+    } else {
+        None
     };
-    let key = Pem {
-        tag: "ed25519 dalek key".to_uppercase(),
-        contents: keypair.to_bytes().to_vec(),
+    if !force {
+        let mut existing = vec![&pubkey_path];
+        if keypair.is_none() {
+            existing.push(&key_path);
+        }
+        existing.retain(|p| p.exists());
+        if !existing.is_empty() {
+            bail!("refusing to overwrite existing keys {existing:?}, pass --force to replace them");
+        }
+    }
+    fs::create_dir_all(&output_path)?;
+    let verifying_key = if let Some(keypair) = keypair {
+        keypair.verifying_key()
+    } else {
+        let keypair = SigningKey::generate(&mut thread_rng());
+        fs::write(key_path, keys::key_pem(&keypair))?;
+        keypair.verifying_key()
     };
-    fs::write(output_path.join("public-key.pem"), pem::encode(&pubkey))?;
-    fs::write(output_path.join("key.pem"), pem::encode(&key))?;
+    fs::write(pubkey_path, keys::public_key_pem(&verifying_key))?;
+    println!(
+        "public key fingerprint: {}",
+        fingerprint(verifying_key.as_bytes())
+    );
     Ok(())
 }
 
-fn pack(
-    library: String,
-    metadata: Option<String>,
-    key: Option<String>,
-    output: Option<String>,
-) -> anyhow::Result<()> {
+fn pack(args: PackArgs) -> anyhow::Result<PackReport> {
+    let PackArgs {
+        library,
+        metadata,
+        from_manifest,
+        key,
+        output,
+        algorithm,
+        no_compress,
+        dict,
+        strict,
+        allow_prerelease,
+        target,
+        resources,
+        ..
+    } = args;
     check_file_exist(&library)?;
     let library = PathBuf::from(library);
-    let metadata = if let Some(path) = metadata {
-        PathBuf::from(path)
+    let mut metadata: PackageMetadata = if let Some(manifest) = from_manifest {
+        check_file_exist(&manifest)?;
+        metadata_from_manifest(manifest)?
     } else {
-        library
-            .parent()
-            .unwrap()
-            .join("../../modules/spider/metadata.json")
+        let path = if let Some(path) = metadata {
+            let path = PathBuf::from(path);
+            check_file_exist(&path)?;
+            path
+        } else {
+            discover_metadata(&library)?
+        };
+        let buf = fs::read(path)?;
+        serde_json::from_slice(buf.as_slice())?
     };
-    check_file_exist(&metadata)?;
+    if let Some(target) = target {
+        metadata.target = Some(target);
+    } else if metadata.target.is_none() {
+        metadata.target = Some(TARGET.to_string());
+    }
+    if strict {
+        metadata.validate_strict()?;
+        if !metadata.version.pre.is_empty() && !allow_prerelease {
+            eprintln!(
+                "warning: {} is a pre-release, pass --allow-prerelease if intended",
+                metadata.version
+            );
+        }
+    } else {
+        metadata.validate()?;
+    }
 
     let key = if let Some(path) = key {
         PathBuf::from(path)
@@ -92,28 +449,472 @@ fn pack(
     };
     check_file_exist(&key)?;
 
-    let keypair = fs::read(key)?;
-    let keypair = pem::parse(keypair)?.contents;
-    let keypair = SigningKey::from_bytes(&keypair.try_into().unwrap());
-
-    let buf = fs::read(metadata)?;
-    let metadata: PackageMetadata = serde_json::from_slice(buf.as_slice())?;
+    let keypair = keys::read_signing_key(key)?;
 
     let out_name = format!("{}.cdp", metadata.name);
     let output = if let Some(path) = output {
-        PathBuf::from(path).join(out_name)
+        output_path(PathBuf::from(path), out_name)
     } else {
         library.parent().unwrap().join(out_name)
     };
 
     let library = fs::read(library)?;
-    let package = Package::new(metadata, library);
+    let expected = LibraryFormat::for_target(metadata.target.as_deref().unwrap_or(TARGET));
+    check_library_format(&library, expected, strict)?;
+    let resources = resources
+        .into_iter()
+        .map(|(name, file)| {
+            check_file_exist(&file)?;
+            Ok((name, fs::read(file)?))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let package = Package::with_resources(metadata, library, resources);
 
-    let exported = package.export(keypair)?;
+    let exported = if let Some(dict) = dict {
+        check_file_exist(&dict)?;
+        let dict = Dictionary::new(fs::read(dict)?);
+        package.sign_with_dictionary(&keypair, &dict)?
+    } else if no_compress {
+        package.sign_with(&keypair, ExportAlgorithm::None)?
+    } else {
+        package.sign_with(&keypair, algorithm.into())?
+    };
 
     fs::create_dir_all(output.parent().unwrap())?;
-    fs::write(output, exported.as_slice())?;
+    fs::write(&output, exported.to_bytes()?)?;
+
+    Ok(PackReport {
+        output,
+        name: package.metadata.name.clone(),
+        version: package.metadata.version.to_string(),
+        digest: package.metadata.digest.clone(),
+        signature: format!("{:x}", exported.signature),
+        algorithm: exported.alog,
+        library_size: package.library_len(),
+        compressed_size: exported.compressed_payload_len(),
+    })
+}
+
+/// `-o` names the package file itself if it has an extension and is not an existing
+/// directory, otherwise the directory it is written to.
+fn output_path(path: PathBuf, out_name: String) -> PathBuf {
+    if !path.is_dir() && path.extension().is_some() {
+        path
+    } else {
+        path.join(out_name)
+    }
+}
+
+/// Catch packing something that is not a native library for the package's target,
+/// e.g. an `.rlib`, which would otherwise only fail when the host opens it.
+fn check_library_format(
+    library: &[u8],
+    expected: LibraryFormat,
+    strict: bool,
+) -> anyhow::Result<()> {
+    let problem = match LibraryFormat::detect(library) {
+        Some(format) if format == expected => return Ok(()),
+        Some(format) => format!("library is {format}, expected {expected}"),
+        None => format!("library is not a recognized native library, expected {expected}"),
+    };
+    if strict {
+        bail!(problem);
+    }
+    eprintln!("warning: {problem}");
+    Ok(())
+}
 
+/// Verify a package with the old key and sign it again with the new one,
+/// keeping its library, metadata and compression.
+fn resign(
+    file: String,
+    old_key: String,
+    new_key: String,
+    output: Option<String>,
+    dict: Option<String>,
+) -> anyhow::Result<()> {
+    check_file_exist(&file)?;
+    check_file_exist(&old_key)?;
+    check_file_exist(&new_key)?;
+    let old_key = keys::read_verifying_key(old_key)?;
+    let new_key = keys::read_signing_key(new_key)?;
+
+    let dict = if let Some(dict) = dict {
+        check_file_exist(&dict)?;
+        Some(Dictionary::new(fs::read(dict)?))
+    } else {
+        None
+    };
+
+    let (metadata, exported) =
+        resign_bytes(&fs::read(&file)?, &old_key, &new_key, dict.as_slice())?;
+    let output = PathBuf::from(output.unwrap_or(file));
+    write_replacing(&output, &exported)?;
+    println!(
+        "re-signed {} {} -> {:?}",
+        metadata.name, metadata.version, output
+    );
+    Ok(())
+}
+
+/// Verify an exported package with the old key and export it again signed with the new one.
+fn resign_bytes(
+    exported: &[u8],
+    old_key: &VerifyingKey,
+    new_key: &SigningKey,
+    dict: &[Dictionary],
+) -> anyhow::Result<(PackageMetadata, Vec<u8>)> {
+    let verified = Package::import_verbose_with(exported, old_key, dict)?;
+    let exported = match (verified.algorithm, dict.first()) {
+        (ExportAlgorithm::ZstdDict(_), Some(dict)) => {
+            verified.package.sign_with_dictionary(new_key, dict)?
+        }
+        (alog, _) => verified.package.sign_with(new_key, alog)?,
+    }
+    .to_bytes()?;
+    Ok((verified.package.metadata, exported))
+}
+
+/// [`resign`] every `.cdp` package in a directory, reporting the ones that
+/// don't verify under the old key instead of stopping at the first.
+fn rotate(
+    old_pub: String,
+    new_key: String,
+    dir: String,
+    output: Option<String>,
+    dict: Option<String>,
+) -> anyhow::Result<()> {
+    check_file_exist(&old_pub)?;
+    check_file_exist(&new_key)?;
+    let old_key = keys::read_verifying_key(old_pub)?;
+    let new_key = keys::read_signing_key(new_key)?;
+    let dict = if let Some(dict) = dict {
+        check_file_exist(&dict)?;
+        Some(Dictionary::new(fs::read(dict)?))
+    } else {
+        None
+    };
+    let output = PathBuf::from(output.unwrap_or_else(|| dir.clone()));
+    fs::create_dir_all(&output)?;
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "cdp") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut rotated = 0;
+    let mut failed = Vec::new();
+    for file in files {
+        let result = fs::read(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| resign_bytes(&bytes, &old_key, &new_key, dict.as_slice()));
+        match result {
+            Ok((metadata, exported)) => {
+                let target = output.join(file.file_name().unwrap());
+                write_replacing(&target, &exported)?;
+                println!(
+                    "re-signed {} {} -> {:?}",
+                    metadata.name, metadata.version, target
+                );
+                rotated += 1;
+            }
+            Err(e) => {
+                eprintln!("failed to re-sign {file:?}: {e}");
+                failed.push(file);
+            }
+        }
+    }
+
+    println!("rotated {rotated} packages, {} failed", failed.len());
+    if !failed.is_empty() {
+        bail!(
+            "{} packages failed to re-sign, those that don't verify under the old key may have been tampered with",
+            failed.len()
+        );
+    }
+    Ok(())
+}
+
+/// Verify each package and sign a lock of their builds.
+fn lock(
+    packages: Vec<String>,
+    key: Option<String>,
+    package_key: Option<String>,
+    dict: Option<String>,
+    output: String,
+) -> anyhow::Result<()> {
+    let key = if let Some(path) = key {
+        PathBuf::from(path)
+    } else {
+        env::current_dir()?.join("key.pem")
+    };
+    check_file_exist(&key)?;
+    let keypair = keys::read_signing_key(key)?;
+    let verifier = if let Some(path) = package_key {
+        check_file_exist(&path)?;
+        keys::read_verifying_key(path)?
+    } else {
+        keypair.verifying_key()
+    };
+    let dict = if let Some(dict) = dict {
+        check_file_exist(&dict)?;
+        Some(Dictionary::new(fs::read(dict)?))
+    } else {
+        None
+    };
+
+    let mut lock = PluginLock::default();
+    for file in packages {
+        check_file_exist(&file)?;
+        let package =
+            Package::import_with_dictionaries(&fs::read(&file)?, &verifier, dict.as_slice())?;
+        if lock.plugins.contains_key(&package.metadata.name) {
+            bail!("{} is listed more than once", package.metadata.name);
+        }
+        println!(
+            "locked {} {} from {file}",
+            package.metadata.name, package.metadata.version
+        );
+        lock.insert(&package.metadata);
+    }
+    fs::write(&output, lock.sign(&keypair)?)?;
+    println!("wrote {output}");
+    Ok(())
+}
+
+/// Verify a package and print what an operator needs to decide whether to load it.
+fn inspect(
+    file: String,
+    key: &VerifyingKey,
+    dict: Option<String>,
+    json: bool,
+) -> anyhow::Result<()> {
+    check_file_exist(&file)?;
+    let dict = if let Some(dict) = dict {
+        check_file_exist(&dict)?;
+        Some(Dictionary::new(fs::read(dict)?))
+    } else {
+        None
+    };
+
+    let verified = Package::import_verbose_with(&fs::read(&file)?, key, dict.as_slice())?;
+    let metadata = &verified.package.metadata;
+    if json {
+        println!("{}", serde_json::to_string_pretty(metadata)?);
+        return Ok(());
+    }
+    println!("{} {}", metadata.name, metadata.version);
+    if let Some(id) = &metadata.id {
+        println!("id: {id}");
+    }
+    if let Some(description) = &metadata.description {
+        println!("{description}");
+    }
+    println!("digest: {}", metadata.digest);
+    if let Some(digest) = &metadata.metadata_digest {
+        println!("metadata digest: {digest}");
+    }
+    println!("compression: {:?}", verified.algorithm);
+    println!("signature scheme: {:?}", verified.scheme);
+    if let Some(target) = &metadata.target {
+        println!("target: {target}");
+    }
+    if let Some(version) = &metadata.plugin_base_version {
+        println!("plugin-base: {version}");
+    }
+    if let Some(version) = &verified.package.embedded_bincode_version {
+        println!("bincode: {version}");
+    }
+    if let Some(version) = &verified.package.embedded_abi_stable_version {
+        println!("abi_stable: {version}");
+    }
+    for dep in metadata.dependencies.iter() {
+        match &dep.id {
+            Some(id) => println!("depends on: {} {} ({id})", dep.name, dep.version),
+            None => println!("depends on: {} {}", dep.name, dep.version),
+        }
+    }
+    if let Some(changelog) = &metadata.changelog {
+        println!();
+        println!("changelog:");
+        println!("{changelog}");
+    }
+    Ok(())
+}
+
+/// Verify every package and report each one, so a whole release is checked at once.
+fn verify_all(
+    mut files: Vec<String>,
+    key: &VerifyingKey,
+    dict: Option<String>,
+    json: bool,
+) -> anyhow::Result<()> {
+    if files.is_empty() {
+        for line in io::stdin().lines() {
+            let line = line?;
+            let file = line.trim();
+            if !file.is_empty() {
+                files.push(file.to_string());
+            }
+        }
+    }
+    if files.is_empty() {
+        bail!("no packages given on the command line or stdin");
+    }
+    let dict = if let Some(dict) = dict {
+        check_file_exist(&dict)?;
+        Some(Dictionary::new(fs::read(dict)?))
+    } else {
+        None
+    };
+
+    let results: Vec<_> = files
+        .iter()
+        .map(|file| {
+            let result = fs::read(file)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| {
+                    Ok(Package::import_with_dictionaries(
+                        &bytes,
+                        key,
+                        dict.as_slice(),
+                    )?)
+                })
+                .map(|package| package.metadata);
+            (file, result)
+        })
+        .collect();
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+
+    if json {
+        let value: Vec<_> = results
+            .iter()
+            .map(|(file, result)| match result {
+                Ok(metadata) => serde_json::json!({
+                    "file": file,
+                    "ok": true,
+                    "name": metadata.name,
+                    "version": metadata.version.to_string(),
+                }),
+                Err(e) => serde_json::json!({
+                    "file": file,
+                    "ok": false,
+                    "error": e.to_string(),
+                }),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else {
+        let width = files.iter().map(|file| file.len()).max().unwrap_or(0);
+        for (file, result) in results.iter() {
+            match result {
+                Ok(metadata) => {
+                    println!(
+                        "{file:<width$}  OK    {} {}",
+                        metadata.name, metadata.version
+                    )
+                }
+                Err(e) => println!("{file:<width$}  FAIL  {e}"),
+            }
+        }
+        println!("{} verified, {failed} failed", results.len() - failed);
+    }
+    if failed > 0 {
+        bail!("{failed} of {} packages failed to verify", results.len());
+    }
+    Ok(())
+}
+
+/// Build metadata from a Cargo.toml, taking the id and dependencies from optional tables:
+///
+/// ```toml
+/// [package.metadata.comet]
+/// id = "com.example.spider"
+///
+/// [package.metadata.comet.dependencies]
+/// other-plugin = "^1.0"
+/// spider = { version = "^2", id = "org.other.spider" }
+/// ```
+///
+/// The resolved plugin-base version is recorded as well.
+fn metadata_from_manifest<P: AsRef<Path>>(manifest: P) -> anyhow::Result<PackageMetadata> {
+    let manifest_path = manifest.as_ref().canonicalize()?;
+    let cargo_meta = MetadataCommand::new()
+        .manifest_path(&manifest_path)
+        .exec()?;
+    let package = cargo_meta
+        .packages
+        .iter()
+        .find(|p| p.manifest_path.as_std_path() == manifest_path);
+    let Some(package) = package else {
+        bail!("{:?} does not describe a package", manifest.as_ref());
+    };
+    let mut builder = PackageMetadata::builder()
+        .name(package.name.as_str())
+        .version(package.version.clone());
+    if let Some(description) = &package.description {
+        builder = builder.description(description.as_str());
+    }
+    if let Some(plugin_base) = cargo_meta.packages.iter().find(|p| p.name == "plugin-base") {
+        builder = builder.plugin_base_version(plugin_base.version.clone());
+    }
+    if let Some(id) = package
+        .metadata
+        .pointer("/comet/id")
+        .and_then(|id| id.as_str())
+    {
+        builder = builder.id(id);
+    }
+    if let Some(deps) = package.metadata.pointer("/comet/dependencies") {
+        let deps: BTreeMap<String, serde_json::Value> = serde_json::from_value(deps.clone())?;
+        for (name, dep) in deps {
+            // either a version requirement, or a table with the version and the id
+            let (req, id) = match &dep {
+                serde_json::Value::String(req) => (req.as_str(), None),
+                serde_json::Value::Object(table) => (
+                    table.get("version").and_then(|v| v.as_str()).unwrap_or(""),
+                    table.get("id").and_then(|v| v.as_str()),
+                ),
+                _ => bail!("dependency {name} must be a version requirement or a table"),
+            };
+            let req = VersionReq::parse(req)?;
+            builder = match id {
+                Some(id) => builder.dependency_with_id(name, id, req),
+                None => builder.dependency(name, req),
+            };
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Look for the metadata next to the library, see [`PackageMetadata::sidecar_paths`].
+fn discover_metadata(library: &Path) -> anyhow::Result<PathBuf> {
+    let candidates = PackageMetadata::sidecar_paths(library);
+    if let Some(found) = candidates.iter().find(|p| p.is_file()) {
+        return Ok(found.clone());
+    }
+    bail!(
+        "cannot find metadata for {:?}, tried {:?}; pass --metadata explicitly",
+        library,
+        candidates
+    );
+}
+
+/// Write through a temporary file in the same directory renamed over `path`, so an
+/// interrupted write leaves the previous package in place rather than a truncated one.
+fn write_replacing(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
     Ok(())
 }
 